@@ -0,0 +1,232 @@
+use std::process::{Command, ExitCode, ExitStatus};
+
+use simple_logger::error;
+
+#[cfg(unix)]
+pub use unix::Forwarding;
+
+/// Runs `cmd` in place of the calling wrapper and waits for it.
+///
+/// On Unix, SIGINT, SIGTERM, SIGHUP and SIGWINCH sent to the wrapper are forwarded
+/// to the child, unless the wrapper was started with them ignored. On Windows, the
+/// wrapper ignores Ctrl+C and leaves it to the child, which shares its console.
+pub fn spawn_and_wait(cmd: &mut Command) -> Result<ExitStatus, ()> {
+    #[cfg(unix)]
+    let forwarding = unix::Forwarding::install();
+    #[cfg(windows)]
+    unsafe {
+        winapi::um::consoleapi::SetConsoleCtrlHandler(None, 1)
+    };
+
+    let mut child = cmd.spawn().map_err(|e| {
+        error!("failed to spawn child process: {}", e);
+    })?;
+    #[cfg(unix)]
+    forwarding.set_child(child.id());
+    child.wait().map_err(|e| {
+        error!("failed to wait for child process: {}", e);
+    })
+}
+
+/// Returns the exit code which reports `status` to the caller of the wrapper.
+///
+/// On Unix, a child killed by a signal makes the wrapper die of the same signal,
+/// or exit with 128 plus its number if it survives it. On Windows, an exit code
+/// which doesn't fit in [`ExitCode`] ends the wrapper right away.
+pub fn exit_code(status: ExitStatus) -> ExitCode {
+    if let Some(code) = status.code() {
+        return match u8::try_from(code) {
+            Ok(x) => ExitCode::from(x),
+            Err(_) => std::process::exit(code),
+        };
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            unix::raise(signal);
+            return ExitCode::from(128u8.saturating_add(signal as u8));
+        }
+    }
+    ExitCode::FAILURE
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+
+    use libc::c_int;
+
+    const FORWARDED: [c_int; 4] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGWINCH];
+
+    /// The pid of the running child, or 0.
+    static CHILD: AtomicI32 = AtomicI32::new(0);
+    /// The signals which arrived before the child was spawned, as a bit set.
+    static PENDING: AtomicU64 = AtomicU64::new(0);
+
+    extern "C" fn forward(signal: c_int) {
+        let pid = CHILD.load(Ordering::SeqCst);
+        if pid > 0 {
+            unsafe { libc::kill(pid, signal) };
+        } else {
+            PENDING.fetch_or(1 << signal, Ordering::SeqCst);
+        }
+    }
+
+    /// The forwarding handlers of [`spawn_and_wait`](super::spawn_and_wait), and the
+    /// actions they replaced, restored on drop. Children spawned by other means use
+    /// it directly: install it, spawn, then pass the pid to `set_child`.
+    pub struct Forwarding(Vec<(c_int, libc::sigaction)>);
+
+    impl Forwarding {
+        pub fn install() -> Self {
+            let mut replaced = Vec::new();
+            for signal in FORWARDED {
+                unsafe {
+                    let mut old: libc::sigaction = std::mem::zeroed();
+                    libc::sigaction(signal, std::ptr::null(), &mut old);
+                    // An ignored signal stays ignored, e.g. SIGHUP under nohup.
+                    if old.sa_sigaction == libc::SIG_IGN {
+                        continue;
+                    }
+                    let mut action: libc::sigaction = std::mem::zeroed();
+                    action.sa_sigaction = forward as extern "C" fn(c_int) as libc::sighandler_t;
+                    action.sa_flags = libc::SA_RESTART;
+                    libc::sigemptyset(&mut action.sa_mask);
+                    libc::sigaction(signal, &action, std::ptr::null_mut());
+                    replaced.push((signal, old));
+                }
+            }
+            Forwarding(replaced)
+        }
+
+        /// Starts forwarding to `pid`, beginning with the signals which arrived while spawning it.
+        pub fn set_child(&self, pid: u32) {
+            CHILD.store(pid as i32, Ordering::SeqCst);
+            let pending = PENDING.swap(0, Ordering::SeqCst);
+            for signal in FORWARDED {
+                if pending & (1 << signal) != 0 {
+                    unsafe { libc::kill(pid as i32, signal) };
+                }
+            }
+        }
+    }
+
+    impl Drop for Forwarding {
+        fn drop(&mut self) {
+            CHILD.store(0, Ordering::SeqCst);
+            PENDING.store(0, Ordering::SeqCst);
+            for (signal, old) in &self.0 {
+                unsafe { libc::sigaction(*signal, old, std::ptr::null_mut()) };
+            }
+        }
+    }
+
+    /// Sends `signal` to the wrapper itself with its default action, without
+    /// dumping a core for the child's crash.
+    pub(super) fn raise(signal: c_int) {
+        unsafe {
+            let limit = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            libc::setrlimit(libc::RLIMIT_CORE, &limit);
+            libc::signal(signal, libc::SIG_DFL);
+            let mut set = std::mem::zeroed();
+            libc::sigemptyset(&mut set);
+            libc::sigaddset(&mut set, signal);
+            libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+            libc::raise(signal);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The test which plays the wrapper in a process of its own, as exit codes and
+    /// signal handlers are process-wide; the others run it with `CHILDLIB_HELPER` set.
+    #[test]
+    fn helper() {
+        let Ok(mode) = std::env::var("CHILDLIB_HELPER") else {
+            return;
+        };
+        match mode.as_str() {
+            #[cfg(windows)]
+            "exit-300" => {
+                let status = spawn_and_wait(Command::new("cmd").args(["/C", "exit 300"])).unwrap();
+                exit_code(status);
+            }
+            #[cfg(unix)]
+            "self-kill-TERM" | "self-kill-INT" => {
+                let script = format!("kill -{} $$", &mode[10..]);
+                let status = spawn_and_wait(Command::new("sh").args(["-c", &script])).unwrap();
+                exit_code(status);
+            }
+            #[cfg(unix)]
+            "forward" => {
+                // The child asks the wrapper to pass a SIGTERM back to it.
+                let script = "trap 'exit 7' TERM; kill -TERM $PPID; while :; do sleep 0.1; done";
+                let status = spawn_and_wait(Command::new("sh").args(["-c", script])).unwrap();
+                std::process::exit(status.code().unwrap_or(1));
+            }
+            #[cfg(unix)]
+            "forward-pending" => {
+                use std::os::unix::process::ExitStatusExt;
+                let forwarding = Forwarding::install();
+                unsafe { libc::raise(libc::SIGTERM) };
+                let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+                forwarding.set_child(child.id());
+                let status = child.wait().unwrap();
+                std::process::exit(if status.signal() == Some(libc::SIGTERM) { 0 } else { 1 });
+            }
+            _ => unreachable!(),
+        }
+        std::process::exit(100);
+    }
+
+    fn run_helper(mode: &str) -> ExitStatus {
+        Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "tests::helper", "--test-threads=1", "--nocapture"])
+            .env("CHILDLIB_HELPER", mode)
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap()
+    }
+
+    #[test]
+    fn exit_code_keeps_small_codes() {
+        let status = spawn_and_wait(Command::new("sh").args(["-c", "exit 3"])).unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert_eq!(exit_code(status), ExitCode::from(3));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn exit_code_passes_large_codes() {
+        assert_eq!(run_helper("exit-300").code(), Some(300));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exit_code_reports_signals() {
+        use std::os::unix::process::ExitStatusExt;
+        for (name, signal) in [("TERM", libc::SIGTERM), ("INT", libc::SIGINT)] {
+            let status = run_helper(&format!("self-kill-{}", name));
+            assert!(
+                status.signal() == Some(signal) || status.code() == Some(128 + signal),
+                "{}: {}",
+                name,
+                status
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn forwards_signals() {
+        assert_eq!(run_helper("forward").code(), Some(7));
+        assert_eq!(run_helper("forward-pending").code(), Some(0));
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use simple_logger::{custom, error, warn};
+use sshhlib::transport::Tool;
+use sshhlib::Record;
+
+pub struct Options {
+    pub retries: u32,
+    pub jobs: usize,
+}
+
+/// Copies `local_file` to `remote_path` on every endpoint; returns whether all copies succeeded.
+pub fn copy_to(records: &[Record], local_file: &str, remote_path: &str, options: &Options) -> bool {
+    run_all(records, options, |record| {
+        let target = format!("{}:{}", record.alias, remote_path);
+        (local_file.to_string(), target)
+    })
+}
+
+/// Copies `remote_file` from every endpoint into `<local_dir>/<alias>/`;
+/// returns whether all copies succeeded.
+pub fn copy_from(
+    records: &[Record],
+    remote_file: &str,
+    local_dir: &str,
+    options: &Options,
+) -> Result<bool, ()> {
+    let local_dir = Path::new(local_dir);
+    for record in records {
+        let dir = local_dir.join(&record.alias);
+        std::fs::create_dir_all(&dir).map_err(|e| {
+            error!("failed to create directory '{}': {}", dir.display(), e);
+        })?;
+    }
+    Ok(run_all(records, options, |record| {
+        let source = format!("{}:{}", record.alias, remote_file);
+        let target = local_dir.join(&record.alias).to_string_lossy().into_owned();
+        (source, target)
+    }))
+}
+
+fn run_all<F>(records: &[Record], options: &Options, endpoints: F) -> bool
+where
+    F: Fn(&Record) -> (String, String) + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        for _ in 0..options.jobs.min(records.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let Some(record) = records.get(i) else {
+                    break;
+                };
+                let (source, target) = endpoints(record);
+                if copy_with_retries(record, &source, &target, options.retries) {
+                    custom!(title=record.alias; "copied");
+                } else {
+                    failed.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+        }
+    });
+
+    let failed = failed.into_inner();
+    if failed > 0 {
+        error!("{} of {} copies failed", failed, records.len());
+        return false;
+    }
+    true
+}
+
+fn copy_with_retries(record: &Record, source: &str, target: &str, retries: u32) -> bool {
+    let attempts = retries + 1;
+    for attempt in 1..=attempts {
+        // Batch mode: concurrent children can't share the terminal for password prompts.
+        let args = ["-q", "-o", "BatchMode=yes", source, target].map(str::to_string);
+        let Ok(mut cmd) = sshhlib::transport::command(Tool::Scp, &args) else {
+            return false;
+        };
+        let result = cmd.stdin(Stdio::null()).status();
+        match result {
+            Ok(status) if status.success() => return true,
+            Ok(status) => match status.code() {
+                Some(code) => warn!(
+                    "'{}': scp exited with code {} (attempt {}/{})",
+                    record.alias, code, attempt, attempts
+                ),
+                None => warn!(
+                    "'{}': scp terminated by signal (attempt {}/{})",
+                    record.alias, attempt, attempts
+                ),
+            },
+            Err(e) => {
+                error!("'{}': failed to spawn child process: {}", record.alias, e);
+                return false;
+            }
+        }
+        if attempt < attempts {
+            std::thread::sleep(Duration::from_secs(attempt as u64));
+        }
+    }
+    error!("'{}': copy failed after {} attempt(s)", record.alias, attempts);
+    false
+}
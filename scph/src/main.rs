@@ -1,69 +1,190 @@
 use std::process::ExitCode;
 
-use clap::Parser;
-use simple_logger::{error, warn};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueHint};
+use simple_logger::error;
+use sshhlib::completion::{self, Shell};
+use sshhlib::transport::Tool;
+use sshhlib::WriteMode;
+
+mod fanout;
 
 #[derive(Parser)]
 struct Arguments {
-    #[clap(short, long, help = "Upsert a new endpoint", conflicts_with_all = &["remove", "rename"])]
-    add: bool,
+    #[clap(subcommand)]
+    command: Option<Command>,
 
-    #[clap(short='d', long, help = "Remove an endpoint", conflicts_with_all = &["add", "rename"])]
-    remove: bool,
+    #[clap(
+        long,
+        global = true,
+        help = "Print the changes to the ssh config as a diff without writing them",
+        conflicts_with = "diff"
+    )]
+    dry_run: bool,
 
-    #[clap(short='m', long, help = "Rename an endpoint", conflicts_with_all = &["add", "remove"])]
-    rename: bool,
+    #[clap(long, global = true, help = "Print the changes to the ssh config as a diff after writing them")]
+    diff: bool,
 
     #[clap(
         trailing_var_arg = true,
         allow_hyphen_values = true,
-        help = "The rest of the arguments",
-        long_help = r#"The positional arguments.
-If '--add' is present, an alias and an endpoint are required.
-If '--rename' is present, an alias and a new alias are required.
-If '--rename' is present, an alias is required.
-Otherwise, these arguments are passed to the 'scp' command."#
+        help = "The arguments passed to 'scp'",
+        long_help = r#"The arguments passed to the 'scp' command; the endpoints are listed if there are none.
+Put '--' first to pass arguments which start with a command name, e.g. 'scph -- to:file .'."#
+    )]
+    args: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[clap(subcommand, about = "Manage the endpoints of the ssh config")]
+    Host(HostCommand),
+
+    #[clap(about = "Copy a local file to multiple endpoints")]
+    To(FanoutArguments),
+
+    #[clap(about = "Collect a remote file from multiple endpoints")]
+    From(FanoutArguments),
+
+    #[clap(about = "Print a completion script, e.g. 'source <(scph completions bash)'")]
+    Completions {
+        #[clap(value_parser = ["bash", "zsh", "fish", "powershell"])]
+        shell: String,
+    },
+
+    #[clap(name = "__complete", hide = true)]
+    Complete {
+        #[clap(allow_hyphen_values = true)]
+        words: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum HostCommand {
+    #[clap(about = "Upsert an endpoint")]
+    Add {
+        #[clap(value_hint = ValueHint::Hostname)]
+        alias: String,
+
+        #[clap(
+            value_hint = ValueHint::Url,
+            help = "The endpoint, '[ssh://][user@]host[:port]'; the user may come from a template"
+        )]
+        uri: String,
+
+        #[clap(long, help = "Comma-separated tags of the endpoint")]
+        tag: Option<String>,
+    },
+
+    #[clap(about = "Remove an endpoint")]
+    Rm {
+        #[clap(value_hint = ValueHint::Hostname)]
+        alias: String,
+    },
+
+    #[clap(about = "Rename an endpoint")]
+    Mv {
+        #[clap(value_hint = ValueHint::Hostname)]
+        alias: String,
+        new_alias: String,
+    },
+
+    #[clap(about = "List the endpoints")]
+    Ls,
+}
+
+#[derive(Args)]
+struct FanoutArguments {
+    #[clap(long, help = "The tag to select endpoints with")]
+    tag: Option<String>,
+
+    #[clap(long, help = "Times to retry a failed copy", default_value = "2")]
+    retries: u32,
+
+    #[clap(long, help = "Maximum number of concurrent copies", default_value = "8")]
+    jobs: usize,
+
+    #[clap(
+        required = true,
+        num_args = 2..=3,
+        value_name = "ARG",
+        help = "An alias pattern, a source and a target",
+        long_help = r#"An alias pattern, a source and a target; the pattern can be omitted if '--tag' is present.
+With 'to', the source is a local file and the target a remote path.
+With 'from', the source is a remote file and the target a local directory."#
     )]
     args: Vec<String>,
 }
 
+fn fanout(args: FanoutArguments, to: bool) -> Result<ExitCode, ()> {
+    let tag = args.tag;
+    let params = args.args;
+    let (pattern, source, target) = match (params.len(), &tag) {
+        (2, Some(_)) => (None, &params[0], &params[1]),
+        (3, _) => (Some(params[0].as_str()), &params[1], &params[2]),
+        _ => {
+            error!("an alias pattern (or '--tag'), a source and a target are required");
+            return Err(());
+        }
+    };
+    let records = sshhlib::select_records(pattern, tag.as_deref())?;
+    if records.is_empty() {
+        error!("no endpoints matched");
+        return Err(());
+    }
+    let options = fanout::Options {
+        retries: args.retries,
+        jobs: args.jobs.max(1),
+    };
+    let ok = if to {
+        fanout::copy_to(&records, source, target, &options)
+    } else {
+        fanout::copy_from(&records, source, target, &options)?
+    };
+    Ok(if ok { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
 fn main() -> ExitCode {
     let result = || -> Result<ExitCode, ()> {
         let args = Arguments::parse();
-
-        if args.add {
-            let args = args.args;
-            if args.len() < 2 {
-                error!("'--add' requires an alias and an endpoint URI");
-                return Err(());
-            }
-            let alias = &args[0];
-            let endpoint_url = &args[1];
-            sshhlib::add_or_modify_entry(alias, endpoint_url)?;
-            return Ok(ExitCode::SUCCESS);
+        if args.dry_run {
+            sshhlib::set_write_mode(WriteMode::DryRun);
+        } else if args.diff {
+            sshhlib::set_write_mode(WriteMode::Diff);
         }
 
-        if args.remove {
-            let args = args.args;
-            if args.len() < 1 {
-                error!("'--remove' requires an alias");
-                return Err(());
+        match args.command {
+            Some(Command::Host(HostCommand::Add { alias, uri, tag })) => {
+                let options = sshhlib::EntryOptions {
+                    tags: tag.map(|x| x.split(',').map(str::to_string).collect()),
+                    ..Default::default()
+                };
+                sshhlib::add_or_modify_entry(&alias, &uri, &options)?;
+                return Ok(ExitCode::SUCCESS);
             }
-            let alias = &args[0];
-            sshhlib::remove_entry(alias)?;
-            return Ok(ExitCode::SUCCESS);
-        }
-
-        if args.rename {
-            let args = args.args;
-            if args.len() < 2 {
-                error!("'--rename' requires an alias and a new name");
-                return Err(());
+            Some(Command::Host(HostCommand::Rm { alias })) => {
+                sshhlib::remove_entry(&alias)?;
+                return Ok(ExitCode::SUCCESS);
             }
-            let alias = &args[0];
-            let new_alias = &args[1];
-            sshhlib::rename_entry(alias, new_alias)?;
-            return Ok(ExitCode::SUCCESS);
+            Some(Command::Host(HostCommand::Mv { alias, new_alias })) => {
+                sshhlib::rename_entry(&alias, &new_alias)?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            Some(Command::Host(HostCommand::Ls)) => {
+                sshhlib::list_entries()?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            Some(Command::To(x)) => return fanout(x, true),
+            Some(Command::From(x)) => return fanout(x, false),
+            Some(Command::Completions { shell }) => {
+                let shell = Shell::from_name(&shell).ok_or(())?;
+                print!("{}", completion::script(shell, "scph"));
+                return Ok(ExitCode::SUCCESS);
+            }
+            Some(Command::Complete { words }) => {
+                completion::complete(Arguments::command(), &words, true)?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            None => {}
         }
 
         let args = args.args;
@@ -72,27 +193,9 @@ fn main() -> ExitCode {
             return Ok(ExitCode::SUCCESS);
         }
 
-        unsafe { winapi::um::consoleapi::SetConsoleCtrlHandler(None, 1) };
-        #[cfg(windows)]
-        let program = "scp.exe";
-        #[cfg(unix)]
-        let program = "scp";
-        let mut child = std::process::Command::new(program)
-            .args(args)
-            .spawn()
-            .map_err(|e| {
-                error!("failed to spawn child process: {}", e);
-            })?;
-        let status = child.wait().map_err(|e| {
-            error!("failed to wait for child process: {}", e);
-        })?;
-        let code = status.code().ok_or_else(|| {
-            warn!("child process terminated by signal");
-        })?;
-        let code = u8::try_from(code).map_err(|_| {
-            warn!("child process returned invalid exit code: {}", code);
-        })?;
-        Ok(ExitCode::from(code))
+        let mut cmd = sshhlib::transport::command(Tool::Scp, &args)?;
+        let status = childlib::spawn_and_wait(&mut cmd)?;
+        Ok(childlib::exit_code(status))
     }();
     result.unwrap_or(ExitCode::FAILURE)
 }
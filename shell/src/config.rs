@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde::Deserialize;
+use simple_logger::error;
+
+/// The content of `~/.config/toolbox/shell.toml`.
+///
+/// ```toml
+/// shells = ["bash.exe", "pwsh.exe"]
+/// profile = "mingw64"
+///
+/// [profiles.mingw64]
+/// set = { MSYSTEM = "MINGW64" }
+/// unset = ["MSYS2_PATH_TYPE"]
+/// prepend = { PATH = ["C:/msys64/mingw64/bin"] }
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The shells to search for, by name in PATH or by path; replaces the default order.
+    #[serde(default)]
+    pub shells: Vec<String>,
+    /// The profile used without `--profile`.
+    pub profile: Option<String>,
+    /// Profiles by name; they replace the predefined ones of the same name.
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+}
+
+/// The changes a profile makes to the environment of the shell, applied in the
+/// order of the fields.
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    unset: Vec<String>,
+    #[serde(default)]
+    set: BTreeMap<String, String>,
+    /// Entries put in front of a path list variable, e.g. PATH.
+    #[serde(default)]
+    prepend: BTreeMap<String, Vec<String>>,
+    /// Entries put at the end of a path list variable.
+    #[serde(default)]
+    append: BTreeMap<String, Vec<String>>,
+}
+
+#[cfg(windows)]
+fn msys_profile(msystem: &str) -> Profile {
+    let set = [
+        ("MSYSTEM", msystem),
+        ("MSYS", "winsymlinks:nativestrict"),
+        ("MSYS2_PATH_TYPE", "inherit"),
+        ("CHERE_INVOKING", "1"),
+    ];
+    Profile {
+        set: set.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        ..Default::default()
+    }
+}
+
+#[cfg(windows)]
+fn predefined_profiles() -> BTreeMap<String, Profile> {
+    ["UCRT64", "MINGW64", "CLANG64"]
+        .iter()
+        .map(|x| (x.to_lowercase(), msys_profile(x)))
+        .collect()
+}
+
+#[cfg(unix)]
+fn predefined_profiles() -> BTreeMap<String, Profile> {
+    BTreeMap::new()
+}
+
+#[cfg(windows)]
+const DEFAULT_PROFILE: Option<&str> = Some("ucrt64");
+#[cfg(unix)]
+const DEFAULT_PROFILE: Option<&str> = None;
+
+#[cfg(windows)]
+fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("USERPROFILE").map(|x| PathBuf::from(x).join(".config"))
+}
+
+#[cfg(unix)]
+fn config_dir() -> Option<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_HOME").filter(|x| !x.is_empty()) {
+        Some(x) => Some(PathBuf::from(x)),
+        None => std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".config")),
+    }
+}
+
+/// Returns the directory of the toolbox config files, `~/.config/toolbox`.
+pub fn toolbox_dir() -> Option<PathBuf> {
+    config_dir().map(|x| x.join("toolbox"))
+}
+
+/// Loads `shell.toml`; a missing file is the same as an empty one.
+pub fn load_config() -> Result<Config, ()> {
+    let Some(path) = toolbox_dir().map(|x| x.join("shell.toml")) else {
+        return Ok(Config::default());
+    };
+    let content = match std::fs::read_to_string(&path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => {
+            error!("failed to read '{}': {}", path.display(), e);
+            return Err(());
+        }
+    };
+    toml::from_str(&content).map_err(|e| {
+        error!("failed to parse '{}': {}", path.display(), e);
+    })
+}
+
+impl Config {
+    /// Returns the profile named `name`, or the default one if `name` is absent.
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<Profile>, ()> {
+        let Some(name) = name.or(self.profile.as_deref()).or(DEFAULT_PROFILE) else {
+            return Ok(None);
+        };
+        let mut profiles = predefined_profiles();
+        profiles.extend(self.profiles.clone());
+        match profiles.remove(name) {
+            Some(x) => Ok(Some(x)),
+            None => {
+                let names = profiles.keys().map(String::as_str).collect::<Vec<_>>();
+                error!("profile '{}' not found, available: {}", name, names.join(", "));
+                Err(())
+            }
+        }
+    }
+}
+
+/// The variables to change in the environment of the shell; `None` removes one.
+#[derive(Clone, Default)]
+pub struct Environment(BTreeMap<String, Option<OsString>>);
+
+impl Environment {
+    /// Returns the value `key` will have, as changed so far or inherited.
+    pub fn get(&self, key: &str) -> Option<OsString> {
+        match self.0.get(key) {
+            Some(x) => x.clone(),
+            None => std::env::var_os(key),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: impl Into<OsString>) {
+        self.0.insert(key.to_string(), Some(value.into()));
+    }
+
+    pub fn unset(&mut self, key: &str) {
+        self.0.insert(key.to_string(), None);
+    }
+
+    /// Adds `entries` to the path list `key`, in front of or after the current
+    /// ones, removing them from where they were.
+    fn extend_list(&mut self, key: &str, entries: &[String], front: bool) -> Result<(), ()> {
+        let current = self.get(key).unwrap_or_default();
+        let current = std::env::split_paths(&current)
+            .filter(|x| !x.as_os_str().is_empty() && !entries.iter().any(|y| x.as_os_str() == y.as_str()));
+        let added = entries.iter().map(PathBuf::from);
+        let list = if front {
+            added.chain(current).collect::<Vec<_>>()
+        } else {
+            current.chain(added).collect::<Vec<_>>()
+        };
+        let value = std::env::join_paths(list).map_err(|e| {
+            error!("failed to update '{}': {}", key, e);
+        })?;
+        self.set(key, value);
+        Ok(())
+    }
+
+    pub fn apply_profile(&mut self, profile: &Profile) -> Result<(), ()> {
+        for key in &profile.unset {
+            self.unset(key);
+        }
+        for (key, value) in &profile.set {
+            self.set(key, value);
+        }
+        for (key, entries) in &profile.prepend {
+            self.extend_list(key, entries, true)?;
+        }
+        for (key, entries) in &profile.append {
+            self.extend_list(key, entries, false)?;
+        }
+        Ok(())
+    }
+
+    pub fn apply_to(&self, cmd: &mut Command) {
+        for (key, value) in &self.0 {
+            match value {
+                Some(x) => cmd.env(key, x),
+                None => cmd.env_remove(key),
+            };
+        }
+    }
+}
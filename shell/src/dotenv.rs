@@ -0,0 +1,274 @@
+use std::collections::BTreeSet;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use simple_logger::{custom, error, warn};
+
+use crate::config::{toolbox_dir, Environment};
+
+/// The names of the env files, in the order they are merged within a directory.
+const FILE_NAMES: &[&str] = &[".env", ".toolbox-env"];
+
+/// Returns the env files from the root down to the working directory, so that
+/// the closer ones override the others.
+fn find_files() -> Result<Vec<PathBuf>, ()> {
+    let cwd = std::env::current_dir().map_err(|e| {
+        error!("failed to get the working directory: {}", e);
+    })?;
+    let mut files = Vec::new();
+    for dir in cwd.ancestors() {
+        for name in FILE_NAMES.iter().rev() {
+            let path = dir.join(name);
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.reverse();
+    Ok(files)
+}
+
+/// Returns the file holding the hashes of the allowed env files.
+fn allowed_path() -> Result<PathBuf, ()> {
+    toolbox_dir().map(|x| x.join("shell-allowed")).ok_or_else(|| {
+        error!("failed to locate the toolbox config directory");
+    })
+}
+
+fn load_allowed() -> Result<BTreeSet<String>, ()> {
+    let path = allowed_path()?;
+    match std::fs::read_to_string(&path) {
+        Ok(x) => Ok(x.lines().map(str::to_string).collect()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(e) => {
+            error!("failed to read '{}': {}", path.display(), e);
+            Err(())
+        }
+    }
+}
+
+/// Hashes the path and the content of an env file, so that both moving and
+/// changing it require a new approval.
+fn file_hash(path: &Path, content: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(path.as_os_str().to_string_lossy().as_bytes());
+    hasher.update([0]);
+    hasher.update(content.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn read_file(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| {
+            warn!("failed to read '{}': {}", path.display(), e);
+        })
+        .ok()
+}
+
+/// Merges the allowed env files from the root down to the working directory into
+/// `env`. A file which is not allowed, not owned by the user, or fails to parse, is
+/// skipped with a warning.
+pub fn load(env: &mut Environment) -> Result<(), ()> {
+    let files = find_files()?;
+    if files.is_empty() {
+        return Ok(());
+    }
+    let allowed = load_allowed()?;
+    for path in files {
+        if !is_owned(&path) {
+            warn!("'{}' is not owned by the current user, skipping", path.display());
+            continue;
+        }
+        let Some(content) = read_file(&path) else {
+            continue;
+        };
+        if !allowed.contains(&file_hash(&path, &content)) {
+            let dir = path.parent().unwrap_or(&path);
+            warn!("'{}' is not allowed, run 'shell --allow' in '{}' to load it", path.display(), dir.display());
+            continue;
+        }
+        let mut merged = env.clone();
+        if parse(&path, &content, &mut merged).is_ok() {
+            *env = merged;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_owned(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).is_ok_and(|x| x.uid() == unsafe { libc::getuid() })
+}
+
+#[cfg(windows)]
+fn is_owned(_path: &Path) -> bool {
+    true
+}
+
+/// Allows the env files of the working directory, as they are now. Those of the
+/// directories above must be allowed from there, so that each approval covers only
+/// files the user is looking at.
+pub fn allow() -> Result<(), ()> {
+    let cwd = std::env::current_dir().map_err(|e| {
+        error!("failed to get the working directory: {}", e);
+    })?;
+    let files = FILE_NAMES
+        .iter()
+        .map(|x| cwd.join(x))
+        .filter(|x| x.is_file())
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        warn!("no '.env' or '.toolbox-env' files in the working directory");
+        return Ok(());
+    }
+    let mut allowed = load_allowed()?;
+    let mut changed = false;
+    for path in files {
+        if !is_owned(&path) {
+            warn!("'{}' is not owned by the current user, skipping", path.display());
+            continue;
+        }
+        let Some(content) = read_file(&path) else {
+            continue;
+        };
+        if allowed.insert(file_hash(&path, &content)) {
+            custom!(title="allowed"; "{}", path.display());
+            changed = true;
+        }
+    }
+    if !changed {
+        return Ok(());
+    }
+
+    let path = allowed_path()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| {
+            error!("failed to create '{}': {}", dir.display(), e);
+        })?;
+    }
+    let content = allowed.into_iter().map(|x| x + "\n").collect::<String>();
+    std::fs::write(&path, content).map_err(|e| {
+        error!("failed to write '{}': {}", path.display(), e);
+    })
+}
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|x| x.is_ascii_alphabetic() || x == '_')
+        && chars.all(|x| x.is_ascii_alphanumeric() || x == '_')
+}
+
+/// Returns the index of the quote closing a value which started with `quote`.
+fn closing_quote(s: &str, quote: char) -> Option<usize> {
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' && quote == '"' {
+            chars.next();
+        } else if c == quote {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Replaces `${VAR}`, `${VAR:-default}` and `$VAR` with their values in `env`,
+/// and with `escapes`, backslash sequences as in a double-quoted value.
+fn expand(s: &str, env: &Environment, escapes: bool) -> String {
+    let mut result = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if escapes => match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some('r') => result.push('\r'),
+                Some(x) => result.push(x),
+                None => result.push('\\'),
+            },
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let name = chars.by_ref().take_while(|x| *x != '}').collect::<String>();
+                let (name, default) = name.split_once(":-").unwrap_or((&name, ""));
+                match env.get(name).filter(|x| !x.is_empty()) {
+                    Some(x) => result.push_str(&x.to_string_lossy()),
+                    None => result.push_str(default),
+                }
+            }
+            '$' if chars.peek().is_some_and(|x| x.is_ascii_alphabetic() || *x == '_') => {
+                let mut name = String::new();
+                while let Some(x) = chars.next_if(|x| x.is_ascii_alphanumeric() || *x == '_') {
+                    name.push(x);
+                }
+                result.push_str(&env.get(&name).unwrap_or_default().to_string_lossy());
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Parses the `KEY=VALUE` lines of an env file into `env`, in order, so that a
+/// value may refer to the keys before it.
+///
+/// Lines may start with `export`; `#` starts a comment. Single-quoted values are
+/// taken literally; double-quoted ones support escapes, and both may span lines.
+fn parse(path: &Path, content: &str, env: &mut Environment) -> Result<(), ()> {
+    let mut lines = content.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").map_or(line, str::trim_start);
+        let Some((key, value)) = line.split_once('=') else {
+            warn!("{}:{}: expected 'KEY=VALUE'", path.display(), number);
+            return Err(());
+        };
+        let key = key.trim_end();
+        if !is_name(key) {
+            warn!("{}:{}: invalid variable name '{}'", path.display(), number, key);
+            return Err(());
+        }
+
+        let value = value.trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let mut raw = value[1..].to_string();
+                let end = loop {
+                    if let Some(end) = closing_quote(&raw, quote) {
+                        break end;
+                    }
+                    let Some((_, next)) = lines.next() else {
+                        warn!("{}:{}: unterminated quote", path.display(), number);
+                        return Err(());
+                    };
+                    raw.push('\n');
+                    raw.push_str(next);
+                };
+                let rest = raw[end + 1..].trim();
+                if !rest.is_empty() && !rest.starts_with('#') {
+                    warn!("{}:{}: unexpected text after the closing quote", path.display(), number);
+                    return Err(());
+                }
+                raw.truncate(end);
+                if quote == '\'' {
+                    raw
+                } else {
+                    expand(&raw, env, true)
+                }
+            }
+            _ => {
+                let value = match value.find(" #") {
+                    Some(i) => &value[..i],
+                    None => value,
+                };
+                expand(value.trim_end(), env, false)
+            }
+        };
+        env.set(key, value);
+    }
+    Ok(())
+}
@@ -1,52 +1,118 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 
 use simple_logger::{error, warn};
 
-const PREDEFINED_ENV: &[(&str, &str)] = &[
-    ("MSYSTEM", "UCRT64"),
-    ("MSYS", "winsymlinks:nativestrict"),
-    ("MSYS2_PATH_TYPE", "inherit"),
-    ("CHERE_INVOKING", "1"),
-];
+use crate::config::{Config, Environment};
+
+mod config;
+mod dotenv;
+
+#[cfg(windows)]
+const SHELLS: &[&str] = &["zsh.exe", "bash.exe", "powershell.exe", "cmd.exe"];
+#[cfg(unix)]
+const SHELLS: &[&str] = &["zsh", "bash", "sh"];
 
 fn main() -> ExitCode {
     main0().unwrap_or(ExitCode::FAILURE)
 }
 
+#[derive(Default)]
+struct Options {
+    profile: Option<String>,
+    allow: bool,
+}
+
+/// Takes the options of the launcher, `--profile <name>` and `--allow`, from the
+/// front of `args`; the rest goes to the shell.
+fn take_options(args: &mut Vec<String>) -> Result<Options, ()> {
+    let mut options = Options::default();
+    loop {
+        let Some(first) = args.first() else {
+            return Ok(options);
+        };
+        if let Some(name) = first.strip_prefix("--profile=") {
+            options.profile = Some(name.to_string());
+            args.remove(0);
+        } else if first == "--profile" {
+            if args.len() < 2 {
+                error!("'--profile' requires a profile name");
+                return Err(());
+            }
+            options.profile = args.drain(..2).nth(1);
+        } else if first == "--allow" {
+            options.allow = true;
+            args.remove(0);
+        } else {
+            return Ok(options);
+        }
+    }
+}
+
 fn main0() -> Result<ExitCode, ()> {
-    let shell = resolve_shell()?;
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let options = take_options(&mut args)?;
+    if options.allow {
+        dotenv::allow()?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    let config = config::load_config()?;
+    let shell = resolve_shell(&config)?;
+    let mut env = Environment::default();
+    if let Some(profile) = config.profile(options.profile.as_deref())? {
+        env.apply_profile(&profile)?;
+    }
+    dotenv::load(&mut env)?;
 
     let mut cmd = std::process::Command::new(&shell);
-    cmd.args(std::env::args().skip(1));
-    cmd.envs(PREDEFINED_ENV.iter().map(|(k, v)| (*k, *v)));
+    #[cfg(unix)]
+    if args.is_empty() {
+        // Without arguments, start a login shell the way login(1) does, with a '-' before argv[0].
+        use std::os::unix::process::CommandExt;
+        let name = shell.file_name().unwrap_or(shell.as_os_str()).to_string_lossy();
+        cmd.arg0(format!("-{}", name));
+        cmd.env("SHELL", &shell);
+    }
+    cmd.args(args);
+    env.apply_to(&mut cmd);
 
-    unsafe { winapi::um::consoleapi::SetConsoleCtrlHandler(None, 1) };
-    let mut child = cmd.spawn().map_err(|e| {
-        error!("failed to spawn child process: {}", e);
-    })?;
-    let status = child.wait().map_err(|e| {
-        error!("failed to wait for child process: {}", e);
-    })?;
-    let code = status.code().ok_or_else(|| {
-        warn!("child process terminated by signal");
-    })?;
-    let code = u8::try_from(code).map_err(|_| {
-        warn!("child process returned invalid exit code: {}", code);
-    })?;
-    Ok(ExitCode::from(code))
+    let status = childlib::spawn_and_wait(&mut cmd)?;
+    Ok(childlib::exit_code(status))
 }
 
-fn resolve_shell() -> Result<PathBuf, ()> {
-    const SHELLS: &[&str] = &["zsh.exe", "bash.exe", "powershell.exe", "cmd.exe"];
+fn resolve_shell(config: &Config) -> Result<PathBuf, ()> {
+    // A configured search order takes precedence over the user's login shell.
+    #[cfg(unix)]
+    if config.shells.is_empty() {
+        for shell in [std::env::var_os("SHELL").map(PathBuf::from), unix::login_shell()]
+            .into_iter()
+            .flatten()
+        {
+            if shell.is_absolute() && is_executable(&shell) {
+                return Ok(shell);
+            }
+        }
+    }
+
     let path = std::env::var_os("PATH").ok_or_else(|| {
         warn!("failed to get PATH environment variable");
     })?;
     let dirs = std::env::split_paths(&path).collect::<Vec<_>>();
-    for shell in SHELLS {
+    let shells = if config.shells.is_empty() {
+        SHELLS.iter().map(|x| x.to_string()).collect()
+    } else {
+        config.shells.clone()
+    };
+    for shell in &shells {
+        if Path::new(shell).is_absolute() {
+            if is_executable(Path::new(shell)) {
+                return Ok(PathBuf::from(shell));
+            }
+            continue;
+        }
         for dir in &dirs {
             let shell_path = dir.join(shell);
-            if shell_path.is_file() {
+            if is_executable(&shell_path) {
                 return Ok(shell_path);
             }
         }
@@ -54,3 +120,33 @@ fn resolve_shell() -> Result<PathBuf, ()> {
     warn!("failed to find any available shell");
     Err(())
 }
+
+#[cfg(windows)]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|x| x.is_file() && x.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::ffi::{CStr, OsStr};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+
+    /// Returns the shell of the user's passwd entry.
+    pub(super) fn login_shell() -> Option<PathBuf> {
+        unsafe {
+            let passwd = libc::getpwuid(libc::getuid());
+            if passwd.is_null() || (*passwd).pw_shell.is_null() {
+                return None;
+            }
+            let shell = CStr::from_ptr((*passwd).pw_shell).to_bytes();
+            (!shell.is_empty()).then(|| PathBuf::from(OsStr::from_bytes(shell)))
+        }
+    }
+}
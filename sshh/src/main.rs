@@ -1,98 +1,558 @@
-use std::process::ExitCode;
+use std::path::PathBuf;
+use std::process::{ExitCode, ExitStatus};
+use std::time::{Duration, Instant};
 
-use clap::Parser;
+use clap::{ArgGroup, Args, CommandFactory, Parser, Subcommand, ValueHint};
 use simple_logger::{error, warn};
+use sshhlib::completion::{self, Shell};
+use sshhlib::transport::Tool;
+use sshhlib::WriteMode;
 
 #[derive(Parser)]
 struct Arguments {
-    #[clap(short, long, help = "Upsert a new entry", conflicts_with_all = &["remove", "rename"])]
-    add: bool,
+    #[clap(subcommand)]
+    command: Option<Command>,
 
-    #[clap(short='d', long, help = "Remove an entry", conflicts_with_all = &["add", "rename"])]
-    remove: bool,
+    #[clap(
+        long,
+        help = "Run another tool with the same aliases instead of 'ssh'",
+        value_parser = ["mosh", "autossh", "sftp", "rsync"]
+    )]
+    transport: Option<String>,
+
+    #[clap(long, help = "Save the ad-hoc endpoint as an alias after a successful connection")]
+    save: Option<String>,
+
+    #[clap(long, help = "Comma-separated tags of the entry, used with '--save'")]
+    tag: Option<String>,
+
+    #[clap(long, help = "Hide the entry after this long, e.g. '12h' or '14d'; used with '--save'")]
+    ttl: Option<String>,
+
+    #[clap(long, help = "Record a transcript of the session, as entries with 'log=true' metadata do")]
+    log: bool,
+
+    #[clap(long, help = "Re-spawn the child process when the connection fails")]
+    reconnect: bool,
 
-    #[clap(short='m', long, help = "Rename an entry", conflicts_with_all = &["add", "remove"])]
-    rename: bool,
+    #[clap(
+        long,
+        help = "Maximum number of connection attempts with '--reconnect'",
+        default_value = "10"
+    )]
+    max_attempts: u32,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Print the changes to the ssh config as a diff without writing them",
+        conflicts_with = "diff"
+    )]
+    dry_run: bool,
+
+    #[clap(long, global = true, help = "Print the changes to the ssh config as a diff after writing them")]
+    diff: bool,
 
     #[clap(
         trailing_var_arg = true,
         allow_hyphen_values = true,
-        help = "The positional arguments",
-        long_help = r#"The positional arguments.
-If '--add' is present, an alias and an endpoint URI are required.
-If '--rename' is present, an alias and a new name are required.
-If '--rename' is present, an alias is required.
-Otherwise, these arguments are passed to the 'ssh' command."#
+        value_hint = ValueHint::Hostname,
+        help = "The arguments passed to 'ssh'",
+        long_help = r#"The arguments passed to the 'ssh' command, or the tool selected by
+'--transport'; the entries are listed if there are none. With '--reconnect',
+the command is run again whenever it exits with 255 (connection failure).
+The destination may be an ad-hoc 'ssh://user@host:port' or 'user@host:port' URI,
+where an IPv6 address is enclosed in '[]'; '--save' then keeps it as an alias.
+Put '--' first to pass arguments which start with a command name, e.g. 'sshh -- host'."#
     )]
     args: Vec<String>,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    #[clap(subcommand, about = "Manage the entries of the ssh config")]
+    Host(HostCommand),
+
+    #[clap(subcommand, about = "Manage named port-forward tunnels")]
+    Tunnel(TunnelCommand),
+
+    #[clap(subcommand, about = "Manage connection sharing through ssh master connections")]
+    Mux(MuxCommand),
+
+    #[clap(subcommand, about = "Manage the keys generated for entries")]
+    Key(KeyCommand),
+
+    #[clap(about = "Check an ssh config file for mistakes")]
+    Lint {
+        #[clap(help = "The ssh config file, '~/.ssh/config' by default")]
+        path: Option<PathBuf>,
+
+        #[clap(long, help = "Remove the lines which have no effect")]
+        fix: bool,
+    },
+
+    #[clap(about = "Restrict the permissions of '~/.ssh', its config and private keys")]
+    FixPerms,
+
+    #[clap(about = "Play back a session transcript")]
+    Replay {
+        #[clap(
+            value_hint = ValueHint::Hostname,
+            help = "A transcript file, or an alias to play its latest transcript"
+        )]
+        target: String,
+    },
+
+    #[clap(about = "Print a completion script, e.g. 'source <(sshh completions bash)'")]
+    Completions {
+        #[clap(value_parser = ["bash", "zsh", "fish", "powershell"])]
+        shell: String,
+    },
+
+    #[clap(name = "__complete", hide = true)]
+    Complete {
+        #[clap(allow_hyphen_values = true)]
+        words: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum HostCommand {
+    #[clap(about = "Upsert an entry")]
+    Add {
+        #[clap(value_hint = ValueHint::Hostname)]
+        alias: String,
+
+        #[clap(
+            value_hint = ValueHint::Url,
+            help = "The endpoint, '[ssh://][user@]host[:port]'; the user may come from a template"
+        )]
+        uri: String,
+
+        #[clap(long, help = "Comma-separated tags of the entry")]
+        tag: Option<String>,
+
+        #[clap(long, help = "Comma-separated jump hosts of the entry")]
+        via: Option<String>,
+
+        #[clap(long, help = "Hide the entry after this long, e.g. '12h' or '14d', '0' removes the expiry")]
+        ttl: Option<String>,
+
+        #[clap(
+            long,
+            help = "The template of '~/.ssh/sshh_config' to apply, instead of the one matching the hostname"
+        )]
+        template: Option<String>,
+
+        #[clap(long, help = "Change to this remote directory on connect, '' removes it")]
+        cd: Option<String>,
+
+        #[clap(
+            long,
+            help = "Run this remote command on connect, e.g. 'tmux new -A -s main', '' removes it"
+        )]
+        run: Option<String>,
+    },
+
+    #[clap(about = "Remove an entry")]
+    Rm {
+        #[clap(value_hint = ValueHint::Hostname)]
+        alias: String,
+    },
+
+    #[clap(about = "Rename an entry")]
+    Mv {
+        #[clap(value_hint = ValueHint::Hostname)]
+        alias: String,
+        new_alias: String,
+    },
+
+    #[clap(about = "Edit the block of an entry in '$VISUAL' or '$EDITOR'")]
+    Edit {
+        #[clap(value_hint = ValueHint::Hostname)]
+        alias: String,
+    },
+
+    #[clap(about = "Show an entry and its jump host chain")]
+    Show {
+        #[clap(value_hint = ValueHint::Hostname)]
+        alias: String,
+    },
+
+    #[clap(about = "List the entries")]
+    Ls,
+
+    #[clap(about = "Show the host key fingerprints of an entry in known_hosts")]
+    Keys {
+        #[clap(value_hint = ValueHint::Hostname)]
+        alias: String,
+    },
+
+    #[clap(about = "Sort the entries by alias, keeping catch-all blocks last")]
+    Sort,
+
+    #[clap(about = "Remove the expired entries")]
+    Prune,
+
+    #[clap(about = "Apply the current version of their template to the entries created from one")]
+    ReapplyTemplate {
+        #[clap(value_hint = ValueHint::Hostname, help = "Only this entry")]
+        alias: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum TunnelCommand {
+    #[clap(about = "Add a tunnel through an entry")]
+    Add {
+        name: String,
+
+        #[clap(value_hint = ValueHint::Hostname)]
+        alias: String,
+
+        #[clap(
+            required = true,
+            help = "'L:[bind:]port:host:hostport', 'R:[bind:]port:host:hostport' or 'D:[bind:]port'"
+        )]
+        forwards: Vec<String>,
+    },
+
+    #[clap(about = "Remove a tunnel")]
+    Rm { name: String },
+
+    #[clap(about = "Start a tunnel in the background")]
+    Up { name: String },
+
+    #[clap(about = "Stop a tunnel")]
+    Down { name: String },
+
+    #[clap(about = "List the tunnels and their state")]
+    Ls,
+}
+
+#[derive(Args)]
+#[clap(group(ArgGroup::new("target").required(true)))]
+struct MuxTarget {
+    #[clap(group = "target", value_hint = ValueHint::Hostname)]
+    alias: Option<String>,
+
+    #[clap(long, group = "target", help = "Every entry instead of an alias")]
+    all: bool,
+}
+
+#[derive(Subcommand)]
+enum MuxCommand {
+    #[clap(about = "Share the connections of an entry")]
+    On(MuxTarget),
+
+    #[clap(about = "Stop sharing the connections of an entry")]
+    Off(MuxTarget),
+
+    #[clap(about = "List the master connections")]
+    Ls,
+
+    #[clap(about = "Stop the master connection of an entry")]
+    Stop(MuxTarget),
+}
+
+#[derive(Subcommand)]
+enum KeyCommand {
+    #[clap(about = "Generate a dedicated key for an entry")]
+    Gen {
+        #[clap(value_hint = ValueHint::Hostname)]
+        alias: String,
+    },
+
+    #[clap(about = "Install the key of an entry on the remote host")]
+    CopyId {
+        #[clap(value_hint = ValueHint::Hostname)]
+        alias: String,
+    },
+
+    #[clap(about = "Report generated keys which no entry uses anymore")]
+    Orphans,
+}
+
+fn split_list(list: Option<String>) -> Option<Vec<String>> {
+    list.map(|x| x.split(',').filter(|x| !x.is_empty()).map(str::to_string).collect())
+}
+
+fn parse_ttl(ttl: Option<String>) -> Result<Option<Duration>, ()> {
+    match ttl {
+        Some(x) => Ok(Some(sshhlib::expiry::parse_ttl(&x)?)),
+        None => Ok(None),
+    }
+}
+
+fn run_host(command: HostCommand) -> Result<(), ()> {
+    match command {
+        HostCommand::Add {
+            alias,
+            uri,
+            tag,
+            via,
+            ttl,
+            template,
+            cd,
+            run,
+        } => {
+            let options = sshhlib::EntryOptions {
+                tags: split_list(tag),
+                via: split_list(via),
+                ttl: parse_ttl(ttl)?,
+                template,
+                cd,
+                run,
+            };
+            sshhlib::add_or_modify_entry(&alias, &uri, &options)
+        }
+        HostCommand::Rm { alias } => sshhlib::remove_entry(&alias),
+        HostCommand::Mv { alias, new_alias } => sshhlib::rename_entry(&alias, &new_alias),
+        HostCommand::Edit { alias } => sshhlib::edit::edit_entry(&alias),
+        HostCommand::Show { alias } => sshhlib::show_entry(&alias),
+        HostCommand::Ls => sshhlib::list_entries(),
+        HostCommand::Keys { alias } => sshhlib::known_hosts::show_keys(&alias),
+        HostCommand::Sort => sshhlib::sort_entries(),
+        HostCommand::Prune => sshhlib::prune_entries(),
+        HostCommand::ReapplyTemplate { alias } => sshhlib::reapply_templates(alias.as_deref()),
+    }
+}
+
+fn run_tunnel(command: TunnelCommand) -> Result<(), ()> {
+    match command {
+        TunnelCommand::Add {
+            name,
+            alias,
+            forwards,
+        } => sshhlib::tunnel::add_tunnel(&name, &alias, &forwards),
+        TunnelCommand::Rm { name } => sshhlib::tunnel::remove_tunnel(&name),
+        TunnelCommand::Up { name } => sshhlib::tunnel::start_tunnel(&name),
+        TunnelCommand::Down { name } => sshhlib::tunnel::stop_tunnel(&name),
+        TunnelCommand::Ls => sshhlib::tunnel::list_tunnels(),
+    }
+}
+
+fn run_mux(command: MuxCommand) -> Result<(), ()> {
+    match command {
+        MuxCommand::On(x) => sshhlib::mux::set_mux(x.alias.as_deref(), true),
+        MuxCommand::Off(x) => sshhlib::mux::set_mux(x.alias.as_deref(), false),
+        MuxCommand::Ls => sshhlib::mux::list_masters(),
+        MuxCommand::Stop(x) => sshhlib::mux::stop_master(x.alias.as_deref()),
+    }
+}
+
+fn run_key(command: KeyCommand) -> Result<(), ()> {
+    match command {
+        KeyCommand::Gen { alias } => sshhlib::keys::generate_key(&alias),
+        KeyCommand::CopyId { alias } => sshhlib::keys::copy_id(&alias),
+        KeyCommand::Orphans => sshhlib::keys::report_orphan_keys(),
+    }
+}
+
 fn main() -> ExitCode {
     let result = || -> Result<ExitCode, ()> {
         let args = Arguments::parse();
-
-        if args.add {
-            let args = args.args;
-            if args.len() < 2 {
-                error!("'--add' requires an alias and an endpoint URI");
-                return Err(());
-            }
-            let alias = &args[0];
-            let endpoint_url = &args[1];
-            sshhlib::add_or_modify_entry(alias, endpoint_url)?;
-            return Ok(ExitCode::SUCCESS);
+        if args.dry_run {
+            sshhlib::set_write_mode(WriteMode::DryRun);
+        } else if args.diff {
+            sshhlib::set_write_mode(WriteMode::Diff);
         }
 
-        if args.remove {
-            let args = args.args;
-            if args.len() < 1 {
-                error!("'--remove' requires an alias");
+        if let Some(command) = args.command {
+            // These only apply to a connection, and would be ignored by a subcommand.
+            let connection_flags = [
+                ("--transport", args.transport.is_some()),
+                ("--save", args.save.is_some()),
+                ("--tag", args.tag.is_some()),
+                ("--ttl", args.ttl.is_some()),
+                ("--log", args.log),
+                ("--reconnect", args.reconnect),
+            ];
+            if let Some((flag, _)) = connection_flags.iter().find(|(_, x)| *x) {
+                error!("'{}' cannot be used with a subcommand", flag);
                 return Err(());
             }
-            let alias = &args[0];
-            sshhlib::remove_entry(alias)?;
-            return Ok(ExitCode::SUCCESS);
-        }
-
-        if args.rename {
-            let args = args.args;
-            if args.len() < 2 {
-                error!("'--rename' requires an alias and a new name");
-                return Err(());
+            match command {
+                Command::Host(x) => run_host(x)?,
+                Command::Tunnel(x) => run_tunnel(x)?,
+                Command::Mux(x) => run_mux(x)?,
+                Command::Key(x) => run_key(x)?,
+                Command::Lint { path, fix } => {
+                    let ok = sshhlib::lint::lint(path.as_deref(), fix)?;
+                    return Ok(if ok { ExitCode::SUCCESS } else { ExitCode::FAILURE });
+                }
+                Command::FixPerms => sshhlib::perms::fix_permissions()?,
+                Command::Replay { target } => sshhlib::record::replay(&target)?,
+                Command::Completions { shell } => {
+                    let shell = Shell::from_name(&shell).ok_or(())?;
+                    print!("{}", completion::script(shell, "sshh"));
+                }
+                Command::Complete { words } => completion::complete(Arguments::command(), &words, false)?,
             }
-            let alias = &args[0];
-            let new_alias = &args[1];
-            sshhlib::rename_entry(alias, new_alias)?;
             return Ok(ExitCode::SUCCESS);
         }
 
+        let tool = args
+            .transport
+            .as_deref()
+            .and_then(Tool::from_name)
+            .unwrap_or(Tool::Ssh);
+        let save = args.save;
+        let tag = args.tag;
+        let ttl = parse_ttl(args.ttl)?;
+        let log = args.log;
+        let reconnect = args.reconnect;
+        let max_attempts = args.max_attempts.max(1);
+
         let args = args.args;
         if args.is_empty()  {
             sshhlib::list_entries()?; 
             return Ok(ExitCode::SUCCESS);
         }
 
-        unsafe { winapi::um::consoleapi::SetConsoleCtrlHandler(None, 1) };
-        #[cfg(windows)]
-        let program = "ssh.exe";
-        #[cfg(unix)]
-        let program = "ssh";
-        let mut child = std::process::Command::new(program)
-            .args(args)
-            .spawn()
-            .map_err(|e| {
-                error!("failed to spawn child process: {}", e);
-            })?;
-        let status = child.wait().map_err(|e| {
-            error!("failed to wait for child process: {}", e);
-        })?;
-        let code = status.code().ok_or_else(|| {
-            warn!("child process terminated by signal");
-        })?;
-        let code = u8::try_from(code).map_err(|_| {
-            warn!("child process returned invalid exit code: {}", code);
-        })?;
-        Ok(ExitCode::from(code))
+        let destination = sshhlib::transport::ssh_destination(&args).map(|x| args[x].clone());
+        if save.is_some() {
+            if !matches!(tool, Tool::Ssh | Tool::Autossh) {
+                error!("'--save' is only supported with 'ssh' and 'autossh'");
+                return Err(());
+            }
+            if !destination.as_ref().is_some_and(|x| x.contains('@')) {
+                error!("'--save' requires a 'user@host[:port]' destination");
+                return Err(());
+            }
+        }
+        let transcript = sshhlib::record::transcript_name(&args, log)?;
+        let args = match tool {
+            Tool::Ssh | Tool::Autossh => sshhlib::transport::translate_uri_args(&args)?,
+            _ => args,
+        };
+
+        let status = if reconnect {
+            supervise(tool, &args, max_attempts, transcript.as_deref())?
+        } else {
+            spawn_and_wait(tool, &args, transcript.as_deref())?
+        };
+        if let (Some(alias), Some(destination)) = (save, destination) {
+            save_endpoint(&alias, &destination, status, tag, ttl)?;
+        }
+        Ok(childlib::exit_code(status))
     }();
     result.unwrap_or(ExitCode::FAILURE)
 }
+
+/// Runs the child, recording its session as `transcript` if given.
+fn spawn_and_wait(tool: Tool, args: &[String], transcript: Option<&str>) -> Result<ExitStatus, ()> {
+    let mut cmd = sshhlib::transport::command(tool, args)?;
+    if let Some(name) = transcript {
+        return sshhlib::record::record(cmd, name);
+    }
+    childlib::spawn_and_wait(&mut cmd)
+}
+
+/// Saves the destination as `alias` unless the connection failed.
+fn save_endpoint(
+    alias: &str,
+    destination: &str,
+    status: ExitStatus,
+    tag: Option<String>,
+    ttl: Option<Duration>,
+) -> Result<(), ()> {
+    if status.code() == Some(CONNECTION_FAILURE) {
+        warn!("connection failed, '{}' not saved", alias);
+        return Ok(());
+    }
+    let options = sshhlib::EntryOptions {
+        tags: split_list(tag),
+        ttl,
+        ..Default::default()
+    };
+    sshhlib::add_or_modify_entry(alias, destination, &options)
+}
+
+/// Exit code of ssh when the connection fails.
+const CONNECTION_FAILURE: i32 = 255;
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+#[cfg(not(test))]
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+#[cfg(test)]
+const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Runs the child until it exits with anything but a connection failure, or
+/// `max_attempts` consecutive attempts have failed. The backoff doubles after
+/// each failure; a session that stayed up longer than [`MAX_BACKOFF`] starts over.
+fn supervise(
+    tool: Tool,
+    args: &[String],
+    max_attempts: u32,
+    transcript: Option<&str>,
+) -> Result<ExitStatus, ()> {
+    let mut attempt = 1;
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let start = Instant::now();
+        let status = spawn_and_wait(tool, args, transcript)?;
+        if status.code() != Some(CONNECTION_FAILURE) {
+            return Ok(status);
+        }
+        if start.elapsed() > MAX_BACKOFF {
+            attempt = 1;
+            backoff = INITIAL_BACKOFF;
+        }
+        if attempt >= max_attempts {
+            error!("connection failed, giving up after {} attempt(s)", attempt);
+            return Ok(status);
+        }
+        warn!(
+            "connection failed, reconnecting in {}s (attempt {}/{})",
+            backoff.as_secs(),
+            attempt + 1,
+            max_attempts
+        );
+        std::thread::sleep(backoff);
+        attempt += 1;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `supervise` against a stub ssh which exits 255 `failures` times, then
+    /// with `code`, and returns the final exit code and the number of attempts.
+    #[cfg(unix)]
+    fn run_stub(failures: u32, code: i32, max_attempts: u32) -> (Option<i32>, u32) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let name = format!("sshh-test-{}-{}-{}", std::process::id(), failures, code);
+        let dir = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        let count = dir.join("count");
+        let stub = dir.join("ssh");
+        let script = format!(
+            "#!/bin/sh\nn=$(($(cat '{count}' 2>/dev/null || echo 0) + 1))\necho $n > '{count}'\n\
+             [ $n -le {failures} ] && exit 255\nexit {code}\n",
+            count = count.display(),
+        );
+        std::fs::write(&stub, script).unwrap();
+        std::fs::set_permissions(&stub, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        std::env::set_var("HOME", &dir);
+        std::env::set_var("SSHH_SSH", &stub);
+        let status = supervise(Tool::Ssh, &["host".to_string()], max_attempts, None).unwrap();
+        let attempts = std::fs::read_to_string(&count).unwrap().trim().parse().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        (status.code(), attempts)
+    }
+
+    // A single test, since the stub is selected through the environment of the process.
+    #[cfg(unix)]
+    #[test]
+    fn supervise_retries_connection_failures() {
+        assert_eq!(run_stub(2, 0, 5), (Some(0), 3));
+        assert_eq!(run_stub(2, 3, 5), (Some(3), 3));
+        assert_eq!(run_stub(10, 0, 3), (Some(255), 3));
+        assert_eq!(run_stub(0, 0, 5), (Some(0), 1));
+        assert_eq!(run_stub(0, 1, 5), (Some(1), 1));
+    }
+}
@@ -0,0 +1,304 @@
+use std::io::Read;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use clap::{Arg, ArgAction, Command, ValueHint};
+
+use crate::transport::{self, Tool};
+use crate::{expiry, load_entries, shell_quote};
+
+/// How long the remote `ls` behind path completion may take.
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Copy)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+}
+
+impl Shell {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bash" => Some(Self::Bash),
+            "zsh" => Some(Self::Zsh),
+            "fish" => Some(Self::Fish),
+            "powershell" => Some(Self::PowerShell),
+            _ => None,
+        }
+    }
+}
+
+// The scripts pass the words up to the cursor to `<bin> __complete`, which
+// prints one candidate per line; '{bin}' is replaced with the binary name.
+
+const BASH: &str = r#"_{bin}() {
+    local line="${COMP_LINE:0:COMP_POINT}" words
+    read -ra words <<< "$line"
+    [[ -z $line || $line == *[[:space:]] ]] && words+=("")
+    local cur="${words[-1]}" IFS=$'\n'
+    COMPREPLY=($({bin} __complete -- "${words[@]:1}" 2>/dev/null))
+    if [[ $cur == *:* && $COMP_WORDBREAKS == *:* ]]; then
+        local prefix="${cur%"${cur##*:}"}"
+        COMPREPLY=("${COMPREPLY[@]#"$prefix"}")
+    fi
+    if [[ ${#COMPREPLY[@]} -eq 1 && ${COMPREPLY[0]} == *[:/] ]]; then
+        compopt -o nospace
+    fi
+}
+complete -o default -F _{bin} {bin}
+"#;
+
+const ZSH: &str = r#"#compdef {bin}
+
+_{bin}() {
+    local -a candidates
+    candidates=(${(f)"$({bin} __complete -- "${(@)words[2,CURRENT]}" 2>/dev/null)"})
+    local candidate
+    for candidate in $candidates; do
+        if [[ $candidate == *[:/] ]]; then
+            compadd -S '' -- "$candidate"
+        else
+            compadd -- "$candidate"
+        fi
+    done
+    (( ${#candidates} )) || _files
+}
+
+compdef _{bin} {bin}
+"#;
+
+const FISH: &str = r#"function __{bin}_complete
+    set -l current (commandline -ct)
+    {bin} __complete -- (commandline -opc)[2..-1] "$current" 2>/dev/null
+end
+complete -c {bin} -a '(__{bin}_complete)'
+"#;
+
+const POWERSHELL: &str = r#"Register-ArgumentCompleter -Native -CommandName '{bin}' -ScriptBlock {
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $words = @($commandAst.CommandElements |
+        Where-Object { $_.Extent.EndOffset -le $cursorPosition } |
+        Select-Object -Skip 1 |
+        ForEach-Object { $_.ToString() })
+    if ($wordToComplete -eq '') { $words += '' }
+    & '{bin}' __complete '--' @words 2>$null | ForEach-Object {
+        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+    }
+}
+"#;
+
+/// Returns the completion script of `bin` for `shell`.
+pub fn script(shell: Shell, bin: &str) -> String {
+    let template = match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => ZSH,
+        Shell::Fish => FISH,
+        Shell::PowerShell => POWERSHELL,
+    };
+    template.replace("{bin}", bin)
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    !matches!(
+        arg.get_action(),
+        ArgAction::SetTrue | ArgAction::SetFalse | ArgAction::Count | ArgAction::Help | ArgAction::Version
+    )
+}
+
+fn find_long<'a>(command: &'a Command, name: &str) -> Option<&'a Arg> {
+    command
+        .get_arguments()
+        .find(|x| x.get_long() == Some(name))
+}
+
+/// Returns the positional argument at `index`, the last one if it takes multiple values.
+fn find_positional(command: &Command, index: usize, passthrough: bool) -> Option<&Arg> {
+    let positionals: Vec<&Arg> = command.get_positionals().collect();
+    if passthrough {
+        return positionals.last().copied();
+    }
+    match positionals.get(index) {
+        Some(x) => Some(x),
+        None => positionals
+            .last()
+            .copied()
+            .filter(|x| x.get_num_args().is_some_and(|x| x.max_values() > 1)),
+    }
+}
+
+/// Prints the candidates for the last of `words`, the arguments after the binary
+/// name as typed so far. Options and subcommands come from the clap definition of
+/// `command`; positionals with a hostname hint complete to aliases. With `remote`,
+/// other positionals complete to `alias:` and then to paths on that host.
+pub fn complete(mut command: Command, words: &[String], remote: bool) -> Result<(), ()> {
+    command.build();
+    let Some((current, previous)) = words.split_last() else {
+        return Ok(());
+    };
+
+    let mut command = &command;
+    let mut positional = 0;
+    // Set after '--', or once the trailing arguments of a passthrough command started.
+    let mut passthrough = false;
+    let mut pending: Option<&Arg> = None;
+    for word in previous {
+        if pending.take().is_some() {
+            continue;
+        }
+        if passthrough {
+            continue;
+        }
+        if word == "--" {
+            passthrough = true;
+            continue;
+        }
+        if let Some(option) = word.strip_prefix("--") {
+            let (name, value) = match option.split_once('=') {
+                Some((name, _)) => (name, true),
+                None => (option, false),
+            };
+            match find_long(command, name) {
+                Some(arg) if takes_value(arg) && !value => pending = Some(arg),
+                Some(_) => {}
+                None => passthrough = has_trailing_args(command),
+            }
+            continue;
+        }
+        if word.len() > 1 && word.starts_with('-') {
+            passthrough = word != "-h" && has_trailing_args(command);
+            continue;
+        }
+        if positional == 0 {
+            if let Some(subcommand) = command.find_subcommand(word) {
+                command = subcommand;
+                continue;
+            }
+        }
+        if find_positional(command, positional, false).is_some_and(|x| x.is_trailing_var_arg_set()) {
+            passthrough = true;
+        }
+        positional += 1;
+    }
+
+    let mut candidates = Vec::new();
+    if let Some(arg) = pending {
+        candidates.extend(value_candidates(arg, current, remote));
+    } else if current.starts_with('-') && !passthrough {
+        for arg in command.get_arguments() {
+            if let (Some(long), false) = (arg.get_long(), arg.is_hide_set()) {
+                candidates.push(format!("--{}", long));
+            }
+        }
+    } else {
+        if positional == 0 && !passthrough {
+            for subcommand in command.get_subcommands() {
+                if !subcommand.is_hide_set() {
+                    candidates.push(subcommand.get_name().to_string());
+                }
+            }
+        }
+        if let Some(arg) = find_positional(command, positional, passthrough) {
+            candidates.extend(value_candidates(arg, current, remote));
+        }
+    }
+
+    for candidate in candidates {
+        if candidate.starts_with(current.as_str()) {
+            println!("{}", candidate);
+        }
+    }
+    Ok(())
+}
+
+fn has_trailing_args(command: &Command) -> bool {
+    command.get_positionals().any(|x| x.is_trailing_var_arg_set())
+}
+
+fn value_candidates(arg: &Arg, current: &str, remote: bool) -> Vec<String> {
+    let values = arg.get_possible_values();
+    if !values.is_empty() {
+        return values.iter().map(|x| x.get_name().to_string()).collect();
+    }
+    match arg.get_value_hint() {
+        ValueHint::Hostname => aliases(),
+        ValueHint::Unknown if remote => match current.split_once(':') {
+            Some((alias, path)) if aliases().iter().any(|x| x == alias) => remote_paths(alias, path),
+            Some(_) => Vec::new(),
+            None => aliases().into_iter().map(|x| format!("{}:", x)).collect(),
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the aliases of the listed entries.
+fn aliases() -> Vec<String> {
+    let now = expiry::now();
+    load_entries()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|x| x.is_listed() && !x.is_expired(now))
+        .map(|x| x.host)
+        .collect()
+}
+
+/// Lists the entries of the directory part of `path` on `alias` with a short,
+/// non-interactive ssh call; nothing is returned if it fails or takes too long.
+fn remote_paths(alias: &str, path: &str) -> Vec<String> {
+    let dir = match path.rfind('/') {
+        Some(i) => &path[..=i],
+        None => "",
+    };
+    let target = if dir.is_empty() { ".".to_string() } else { shell_quote(dir) };
+    let args = [
+        "-o".to_string(),
+        "BatchMode=yes".to_string(),
+        "-o".to_string(),
+        format!("ConnectTimeout={}", REMOTE_TIMEOUT.as_secs()),
+        alias.to_string(),
+        format!("ls -1Ap -- {}", target),
+    ];
+    let Ok(mut cmd) = transport::command(Tool::Ssh, &args) else {
+        return Vec::new();
+    };
+    let Ok(mut child) = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    else {
+        return Vec::new();
+    };
+
+    // Read in the background so a large listing cannot fill the pipe and stall the child.
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut output = String::new();
+        let _ = stdout.read_to_string(&mut output);
+        output
+    });
+    let deadline = Instant::now() + REMOTE_TIMEOUT;
+    let success = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status.success(),
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(20)),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                break false;
+            }
+        }
+    };
+    // A grandchild may still hold the pipe open after a kill, so only wait for the reader on success.
+    if !success {
+        return Vec::new();
+    }
+    reader
+        .join()
+        .unwrap_or_default()
+        .lines()
+        .filter(|x| !x.is_empty())
+        .map(|x| format!("{}:{}{}", alias, dir, x))
+        .collect()
+}
@@ -0,0 +1,107 @@
+/// Lines of context around each change, as `diff -u` does.
+const CONTEXT: usize = 3;
+
+enum Op {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Computes the line operations turning `a` into `b` from their longest common subsequence.
+fn diff_lines(a: &[&str], b: &[&str]) -> Vec<Op> {
+    let (n, m) = (a.len(), b.len());
+    let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[at(i, j)] = if a[i] == b[j] {
+                lcs[at(i + 1, j + 1)] + 1
+            } else {
+                lcs[at(i + 1, j)].max(lcs[at(i, j + 1)])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(n.max(m));
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a[i] == b[j] {
+            ops.push(Op::Equal(i, j));
+            i += 1;
+            j += 1;
+        } else if lcs[at(i + 1, j)] >= lcs[at(i, j + 1)] {
+            ops.push(Op::Delete(i));
+            i += 1;
+        } else {
+            ops.push(Op::Insert(j));
+            j += 1;
+        }
+    }
+    ops.extend((i..n).map(Op::Delete));
+    ops.extend((j..m).map(Op::Insert));
+    ops
+}
+
+/// Renders a unified diff between `old` and `new`, or an empty string if they are equal.
+pub(crate) fn unified_diff(old: &str, new: &str, name: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    let ops = diff_lines(&a, &b);
+
+    // Group the changes into hunks, merging those whose contexts overlap.
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| !matches!(x, Op::Equal(..)))
+        .map(|(i, _)| i)
+        .collect();
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for i in changes {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let mut result = format!("--- {}\n+++ {}\n", name, name);
+    for (start, end) in hunks {
+        let ops = &ops[start..end];
+        let (mut old_start, mut new_start) = (None, None);
+        let (mut old_len, mut new_len) = (0, 0);
+        let mut body = String::new();
+        for op in ops {
+            match *op {
+                Op::Equal(i, j) => {
+                    old_start.get_or_insert(i);
+                    new_start.get_or_insert(j);
+                    old_len += 1;
+                    new_len += 1;
+                    body.push_str(&format!(" {}\n", a[i]));
+                }
+                Op::Delete(i) => {
+                    old_start.get_or_insert(i);
+                    old_len += 1;
+                    body.push_str(&format!("-{}\n", a[i]));
+                }
+                Op::Insert(j) => {
+                    new_start.get_or_insert(j);
+                    new_len += 1;
+                    body.push_str(&format!("+{}\n", b[j]));
+                }
+            }
+        }
+        // With context lines, a side is only empty if its whole file is empty.
+        let old_start = old_start.map_or(0, |x| x + 1);
+        let new_start = new_start.map_or(0, |x| x + 1);
+        result.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n{}",
+            old_start, old_len, new_start, new_len, body
+        ));
+    }
+    result
+}
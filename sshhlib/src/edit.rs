@@ -0,0 +1,202 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use simple_logger::{custom, error, warn};
+
+use crate::{
+    confirm, data_dir, home_dir, open_and_load_config, parse_entries, perms, report_not_found,
+    write_config, Block,
+};
+
+/// Returns the byte range of the `Host` block of `alias` in `content`, from its
+/// `Host` line up to the next `Host` or `Match` line.
+fn find_block(content: &str, alias: &str) -> Option<Range<usize>> {
+    let mut start = None;
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        let mut words = line.split_whitespace();
+        let keyword = words.next().unwrap_or_default().to_lowercase();
+        if keyword == "host" || keyword == "match" {
+            if let Some(start) = start {
+                return Some(start..offset);
+            }
+            let value = line.trim_start()[keyword.len()..].trim();
+            if keyword == "host" && value == alias {
+                start = Some(offset);
+            }
+        }
+        offset += line.len();
+    }
+    start.map(|x| x..content.len())
+}
+
+/// Checks that `block` is a single host block with a hostname and a user, which
+/// may replace the one at `range` of `content`.
+fn check_block(block: &str, content: &str, range: &Range<usize>) -> Result<(), ()> {
+    let entries = parse_entries(block)?;
+    if entries[0].options().next().is_some() {
+        error!("options before the 'Host' line would apply to every host");
+        return Err(());
+    }
+    let [_, entry] = entries.as_slice() else {
+        error!("the block must contain exactly one 'Host' or 'Match' line");
+        return Err(());
+    };
+    if entry.block != Block::Host || entry.is_wildcard() {
+        error!("the block must start with 'Host <alias>'");
+        return Err(());
+    }
+    if entry.hostname.is_empty() {
+        error!("entry '{}' has no hostname", entry.host);
+        return Err(());
+    }
+    if entry.user.is_empty() {
+        error!("entry '{}' has no user", entry.host);
+        return Err(());
+    }
+
+    // ssh matches aliases case-insensitively
+    let rest = format!("{}{}", &content[..range.start], &content[range.end..]);
+    if let Some(other) = parse_entries(&rest)?
+        .iter()
+        .find(|x| x.block == Block::Host && x.host.eq_ignore_ascii_case(&entry.host))
+    {
+        error!("entry '{}' already exists", other.host);
+        return Err(());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+const DEFAULT_EDITOR: &str = "notepad";
+#[cfg(unix)]
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Opens `path` in `$VISUAL`, `$EDITOR` or the default editor, and waits for it to close.
+fn run_editor(path: &Path) -> Result<(), ()> {
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|x| std::env::var(x).ok())
+        .find(|x| !x.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string());
+    // The editor may come with arguments, e.g. 'code --wait'.
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or(DEFAULT_EDITOR);
+    let status = Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+        .map_err(|e| {
+            error!("failed to run editor '{}': {}", editor, e);
+        })?;
+    if !status.success() {
+        error!("editor '{}' failed: {}", editor, status);
+        return Err(());
+    }
+    Ok(())
+}
+
+/// Creates a new file for `content` in a directory private to the user, rather than
+/// in the shared temporary directory, where others could read or replace it.
+fn create_temp_file(content: &str) -> Result<PathBuf, ()> {
+    let dir = data_dir()?.join("edit");
+    perms::create_dir(&dir).map_err(|e| {
+        error!("failed to create '{}': {}", dir.display(), e);
+    })?;
+    let mut suffix = 1;
+    loop {
+        let path = match suffix {
+            1 => dir.join(format!("sshh-edit-{}.conf", std::process::id())),
+            n => dir.join(format!("sshh-edit-{}-{}.conf", std::process::id(), n)),
+        };
+        match perms::private_file(&mut File::options()).write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                return std::io::Write::write_all(&mut file, content.as_bytes())
+                    .map(|_| path.clone())
+                    .map_err(|e| {
+                        error!("failed to write '{}': {}", path.display(), e);
+                    });
+            }
+            // An edited block kept by an earlier run.
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => suffix += 1,
+            Err(e) => {
+                error!("failed to create '{}': {}", path.display(), e);
+                return Err(());
+            }
+        }
+    }
+}
+
+/// Opens the block of `alias`, as written in the ssh config, in an editor. The
+/// edited block replaces the original one if it is valid and the ssh config did
+/// not change in the meantime.
+pub fn edit_entry(alias: &str) -> Result<(), ()> {
+    let path = home_dir()?.join(".ssh").join("config");
+    let content = match std::fs::read_to_string(&path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            error!("failed to read ssh config file: {}", e);
+            return Err(());
+        }
+    };
+    let Some(range) = find_block(&content, alias) else {
+        report_not_found(alias);
+        return Err(());
+    };
+    let original = &content[range.clone()];
+
+    let temp = create_temp_file(original)?;
+    let remove_temp = || {
+        let _ = std::fs::remove_file(&temp);
+    };
+
+    let mut block = loop {
+        run_editor(&temp).inspect_err(|_| remove_temp())?;
+        let block = std::fs::read_to_string(&temp).map_err(|e| {
+            error!("failed to read '{}': {}", temp.display(), e);
+        })?;
+        if block.trim().is_empty() {
+            warn!("the block is empty, nothing changed");
+            remove_temp();
+            return Ok(());
+        }
+        if check_block(&block, &content, &range).is_ok() {
+            break block;
+        }
+        if !confirm("edit again?") {
+            error!("the edited block is kept in '{}'", temp.display());
+            return Err(());
+        }
+    };
+    if block == original {
+        warn!("no changes");
+        remove_temp();
+        return Ok(());
+    }
+    if !block.ends_with('\n') {
+        block.push('\n');
+    }
+
+    let (mut file, _) = open_and_load_config(false, true)?.ok_or_else(|| {
+        error!("ssh config file removed while editing, the edited block is kept in '{}'", temp.display());
+    })?;
+    let mut current = String::new();
+    file.seek(SeekFrom::Start(0))
+        .and_then(|_| file.read_to_string(&mut current))
+        .map_err(|e| {
+            error!("failed to read ssh config file: {}", e);
+        })?;
+    if current != content {
+        error!("ssh config file changed while editing, the edited block is kept in '{}'", temp.display());
+        return Err(());
+    }
+    let content = format!("{}{}{}", &content[..range.start], block, &content[range.end..]);
+    write_config(&mut file, &content)?;
+    remove_temp();
+    custom!(title=alias; "updated");
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use simple_logger::error;
+
+/// The longest time to live, about 100 years.
+const MAX_TTL: u64 = 100 * 365 * 24 * 60 * 60;
+
+/// Parses a time to live like `30m`, `12h`, `14d` or `2w`; a bare number is in days.
+pub fn parse_ttl(ttl: &str) -> Result<Duration, ()> {
+    let (number, unit) = match ttl.find(|x: char| !x.is_ascii_digit()) {
+        Some(i) => ttl.split_at(i),
+        None => (ttl, "d"),
+    };
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            error!("invalid ttl '{}', the unit must be one of 's', 'm', 'h', 'd' or 'w'", ttl);
+            return Err(());
+        }
+    };
+    let number: u64 = number.parse().map_err(|_| {
+        error!("invalid ttl '{}', not a number", ttl);
+    })?;
+    match number.checked_mul(seconds).filter(|x| *x <= MAX_TTL) {
+        Some(x) => Ok(Duration::from_secs(x)),
+        None => {
+            error!("invalid ttl '{}', it must not exceed 100 years", ttl);
+            Err(())
+        }
+    }
+}
+
+/// Returns the seconds since the unix epoch.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or_default()
+}
+
+// The conversions between days since the epoch and civil dates follow
+// http://howardhinnant.github.io/date_algorithms.html
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Formats a unix timestamp as `YYYY-MM-DDTHH:MM:SSZ`.
+pub(crate) fn format_timestamp(timestamp: u64) -> String {
+    let timestamp = timestamp as i64;
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(86400));
+    let seconds = timestamp.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Parses a timestamp written by [`format_timestamp`].
+pub(crate) fn parse_timestamp(s: &str) -> Option<u64> {
+    let s = s.strip_suffix('Z')?;
+    let (date, time) = s.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|x| x.parse::<i64>());
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time.splitn(3, ':').map(|x| x.parse::<i64>());
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let timestamp = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    u64::try_from(timestamp).ok()
+}
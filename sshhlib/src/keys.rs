@@ -0,0 +1,144 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use simple_logger::{custom, error, warn};
+
+use crate::transport::{self, Tool};
+use crate::{
+    expand_home, home_dir, load_entries, override_system_entry, update_entries, write_mode,
+    WriteMode,
+};
+
+/// Keys generated by sshh are named `id_ed25519_sshh_<alias>`.
+const KEY_PREFIX: &str = "id_ed25519_sshh_";
+
+/// Appends the key read from stdin to `authorized_keys`, unless it's already there.
+const INSTALL_SCRIPT: &str = r#"umask 077; mkdir -p .ssh && k="$(cat)" && { grep -qxF "$k" .ssh/authorized_keys 2>/dev/null || printf '%s\n' "$k" >> .ssh/authorized_keys; }"#;
+
+/// Generates a dedicated ed25519 key for `alias` and makes its entry use only that key.
+///
+/// A dry run only shows the change to the entry, without running ssh-keygen.
+pub fn generate_key(alias: &str) -> Result<(), ()> {
+    let entries = load_entries()?;
+    let entry = entries.iter().find(|x| x.has_alias(alias)).ok_or_else(|| {
+        error!("entry '{}' not found", alias);
+    })?;
+    let name = format!("{}{}", KEY_PREFIX, alias);
+    let path = home_dir()?.join(".ssh").join(&name);
+    if path.exists() {
+        error!("key file '{}' already exists", path.display());
+        return Err(());
+    }
+
+    let comment = format!("{}@{}", entry.user, alias);
+    let args = [
+        "-t".to_string(),
+        "ed25519".to_string(),
+        "-C".to_string(),
+        comment,
+        "-f".to_string(),
+        path.to_string_lossy().into_owned(),
+    ];
+    let dry_run = write_mode() == WriteMode::DryRun;
+    if !dry_run {
+        let status = transport::command(Tool::SshKeygen, &args)?
+            .status()
+            .map_err(|e| {
+                error!("failed to spawn child process: {}", e);
+            })?;
+        if !status.success() {
+            error!("ssh-keygen failed: {}", status);
+            return Err(());
+        }
+    }
+
+    update_entries(|entries| {
+        override_system_entry(entries, alias);
+        let entry = entries
+            .iter_mut()
+            .find(|x| x.has_alias(alias))
+            .ok_or_else(|| {
+                error!("entry '{}' not found", alias);
+            })?;
+        entry.set_option("IdentityFile", Some(format!("~/.ssh/{}", name)));
+        entry.set_option("IdentitiesOnly", Some("yes".to_string()));
+        Ok(())
+    })?;
+    if dry_run {
+        return Ok(());
+    }
+    custom!(title=alias; "now uses '{}'", path.display());
+    report_orphan_keys()
+}
+
+/// Installs the public key of the entry's `IdentityFile` into the remote `authorized_keys`.
+pub fn copy_id(alias: &str) -> Result<(), ()> {
+    let entries = load_entries()?;
+    let entry = entries.iter().find(|x| x.has_alias(alias)).ok_or_else(|| {
+        error!("entry '{}' not found", alias);
+    })?;
+    let identity = entry.option("IdentityFile").ok_or_else(|| {
+        error!("entry '{}' has no identity file, run 'sshh key gen' first", alias);
+    })?;
+    let mut path = expand_home(identity.trim_matches('"'))?.into_os_string();
+    path.push(".pub");
+    let path = PathBuf::from(path);
+    let key = std::fs::read_to_string(&path).map_err(|e| {
+        error!("failed to read '{}': {}", path.display(), e);
+    })?;
+
+    // The new key isn't authorized yet, let ssh fall back to other keys and passwords.
+    let args = [
+        "-o".to_string(),
+        "IdentitiesOnly=no".to_string(),
+        alias.to_string(),
+        INSTALL_SCRIPT.to_string(),
+    ];
+    let mut child = transport::command(Tool::Ssh, &args)?
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            error!("failed to spawn child process: {}", e);
+        })?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(key.trim().as_bytes()).map_err(|e| {
+            error!("failed to write to child process: {}", e);
+        })?;
+    }
+    let status = child.wait().map_err(|e| {
+        error!("failed to wait for child process: {}", e);
+    })?;
+    if !status.success() {
+        error!("failed to install the key on '{}': {}", alias, status);
+        return Err(());
+    }
+    custom!(title=alias; "'{}' installed", path.display());
+    Ok(())
+}
+
+/// Reports the keys generated by sshh which no entry references anymore.
+pub fn report_orphan_keys() -> Result<(), ()> {
+    let dir = home_dir()?.join(".ssh");
+    let read_dir = match std::fs::read_dir(&dir) {
+        Ok(x) => x,
+        Err(_) => return Ok(()),
+    };
+    let mut referenced = Vec::new();
+    for entry in load_entries()? {
+        if let Some(x) = entry.option("IdentityFile") {
+            referenced.push(expand_home(x.trim_matches('"'))?);
+        }
+    }
+    for file in read_dir.flatten() {
+        let name = file.file_name().to_string_lossy().into_owned();
+        if !name.starts_with(KEY_PREFIX) || name.ends_with(".pub") {
+            continue;
+        }
+        let path = file.path();
+        if !referenced.contains(&path) {
+            warn!("key '{}' is not used by any entry", path.display());
+        }
+    }
+    Ok(())
+}
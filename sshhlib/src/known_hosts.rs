@@ -0,0 +1,251 @@
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use simple_logger::{custom, error, warn};
+
+use crate::{confirm, home_dir, load_entries, match_pattern, write_mode, WriteMode};
+
+enum Hosts {
+    /// Comma-separated patterns, e.g. `host,1.2.3.4,[host]:2222`.
+    Plain(String),
+    /// `|1|salt|hmac` written with `HashKnownHosts yes`.
+    Hashed { salt: Vec<u8>, hash: Vec<u8> },
+}
+
+struct KnownHost {
+    /// The 1-based line number in the file.
+    line: usize,
+    marker: Option<String>,
+    hosts: Hosts,
+    key_type: String,
+    key: String,
+}
+
+impl KnownHost {
+    fn matches(&self, host: &str, port: u16) -> bool {
+        let name = host_name(host, port);
+        match &self.hosts {
+            Hosts::Plain(patterns) => {
+                let mut matched = false;
+                for pattern in patterns.split(',') {
+                    if let Some(pattern) = pattern.strip_prefix('!') {
+                        if match_pattern(pattern, &name) {
+                            return false;
+                        }
+                    } else if match_pattern(pattern, &name) {
+                        matched = true;
+                    }
+                }
+                matched
+            }
+            Hosts::Hashed { salt, hash } => {
+                let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(salt) else {
+                    return false;
+                };
+                mac.update(name.as_bytes());
+                mac.verify_slice(hash).is_ok()
+            }
+        }
+    }
+
+    /// Whether the line records a key of exactly this endpoint, as `ssh-keygen -R`
+    /// would remove it: no marker, and the name itself rather than a wildcard.
+    fn names(&self, host: &str, port: u16) -> bool {
+        if self.marker.is_some() {
+            return false;
+        }
+        match &self.hosts {
+            // ssh compares host names case-insensitively.
+            Hosts::Plain(patterns) => {
+                let name = host_name(host, port).to_lowercase();
+                let negated = patterns
+                    .split(',')
+                    .filter_map(|x| x.strip_prefix('!'))
+                    .any(|x| match_pattern(&x.to_lowercase(), &name));
+                !negated && patterns.split(',').any(|x| x.eq_ignore_ascii_case(&name))
+            }
+            Hosts::Hashed { .. } => self.matches(&host.to_lowercase(), port),
+        }
+    }
+
+    fn fingerprint(&self) -> String {
+        match STANDARD.decode(&self.key) {
+            Ok(blob) => format!("SHA256:{}", STANDARD_NO_PAD.encode(Sha256::digest(blob))),
+            Err(_) => "invalid key".to_string(),
+        }
+    }
+}
+
+/// Returns the name ssh records for the endpoint: `host` on port 22, `[host]:port` otherwise.
+fn host_name(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_string()
+    } else {
+        format!("[{}]:{}", host, port)
+    }
+}
+
+fn parse_line(line: usize, content: &str) -> Option<KnownHost> {
+    let content = content.trim();
+    if content.is_empty() || content.starts_with('#') {
+        return None;
+    }
+    let mut words = content.split_whitespace();
+    let mut first = words.next()?;
+    let mut marker = None;
+    if first.starts_with('@') {
+        marker = Some(first.to_string());
+        first = words.next()?;
+    }
+    let hosts = match first.strip_prefix("|1|") {
+        Some(hashed) => {
+            let (salt, hash) = hashed.split_once('|')?;
+            Hosts::Hashed {
+                salt: STANDARD.decode(salt).ok()?,
+                hash: STANDARD.decode(hash).ok()?,
+            }
+        }
+        None => Hosts::Plain(first.to_string()),
+    };
+    Some(KnownHost {
+        line,
+        marker,
+        hosts,
+        key_type: words.next()?.to_string(),
+        key: words.next()?.to_string(),
+    })
+}
+
+fn known_hosts_path() -> Result<PathBuf, ()> {
+    Ok(home_dir()?.join(".ssh").join("known_hosts"))
+}
+
+fn read_known_hosts() -> Result<Option<String>, ()> {
+    let path = known_hosts_path()?;
+    match std::fs::read_to_string(&path) {
+        Ok(x) => Ok(Some(x)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => {
+            error!("failed to read known_hosts file: {}", e);
+            Err(())
+        }
+    }
+}
+
+fn find_keys(content: &str, host: &str, port: u16) -> Vec<KnownHost> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, x)| parse_line(i + 1, x))
+        .filter(|x| x.matches(host, port))
+        .collect()
+}
+
+pub fn show_keys(alias: &str) -> Result<(), ()> {
+    let entries = load_entries()?;
+    let entry = entries.iter().find(|x| x.has_alias(alias)).ok_or_else(|| {
+        error!("entry '{}' not found", alias);
+    })?;
+    let keys = read_known_hosts()?
+        .map(|x| find_keys(&x, &entry.hostname, entry.port()))
+        .unwrap_or_default();
+    let name = host_name(&entry.hostname, entry.port());
+    if keys.is_empty() {
+        warn!("no keys of '{}' on record", name);
+        return Ok(());
+    }
+    for key in &keys {
+        let hashed = matches!(key.hosts, Hosts::Hashed { .. });
+        let marker = key
+            .marker
+            .as_ref()
+            .map(|x| format!(", {}", x))
+            .unwrap_or_default();
+        custom!(
+            title=key.key_type;
+            "{} (line {}{}{})",
+            key.fingerprint(),
+            key.line,
+            if hashed { ", hashed" } else { "" },
+            marker
+        );
+    }
+    Ok(())
+}
+
+/// Offers to remove the keys recorded for `host:port`, unless another entry still connects to it.
+pub(crate) fn offer_to_remove_keys(host: &str, port: u16) -> Result<(), ()> {
+    if write_mode() == WriteMode::DryRun {
+        return Ok(());
+    }
+    if load_entries()?
+        .iter()
+        .any(|x| x.hostname == host && x.port() == port)
+    {
+        return Ok(());
+    }
+    let Some(content) = read_known_hosts()? else {
+        return Ok(());
+    };
+    let keys = content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, x)| parse_line(i + 1, x))
+        .filter(|x| x.names(host, port))
+        .collect::<Vec<_>>();
+    if keys.is_empty() {
+        return Ok(());
+    }
+    let name = host_name(host, port);
+    let prompt = format!(
+        "remove {} stale key(s) of '{}' from known_hosts?",
+        keys.len(),
+        name
+    );
+    if !confirm(&prompt) {
+        return Ok(());
+    }
+
+    let mut result = String::with_capacity(content.len());
+    for (i, line) in content.lines().enumerate() {
+        if keys.iter().any(|x| x.line == i + 1) {
+            continue;
+        }
+        result.push_str(line);
+        result.push('\n');
+    }
+    std::fs::write(known_hosts_path()?, result).map_err(|e| {
+        error!("failed to write known_hosts file: {}", e);
+    })?;
+    custom!(title=name; "{} key(s) removed", keys.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_only_plain_literal_lines() {
+        let content = "@cert-authority *.example.com ssh-ed25519 AAAA\n\
+                       @revoked web.example.com ssh-ed25519 AAAB\n\
+                       * ssh-ed25519 AAAC\n\
+                       *.example.com ssh-ed25519 AAAD\n\
+                       other,WEB.example.com ssh-ed25519 AAAE\n\
+                       web.example.com,!web.* ssh-ed25519 AAAF\n\
+                       [web.example.com]:2222 ssh-ed25519 AAAG\n";
+        let lines = content
+            .lines()
+            .enumerate()
+            .filter_map(|(i, x)| parse_line(i + 1, x))
+            .filter(|x| x.names("web.example.com", 22))
+            .map(|x| x.line)
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [5]);
+    }
+}
@@ -1,9 +1,27 @@
 use std::fs::File;
-use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::io::{ErrorKind, IsTerminal, Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
 
 use simple_logger::{custom, error, warn};
 
+use crate::settings::{load_settings, Template};
+
+pub mod completion;
+mod diff;
+pub mod edit;
+pub mod expiry;
+pub mod keys;
+pub mod known_hosts;
+pub mod lint;
+pub mod mux;
+pub mod perms;
+pub mod record;
+mod settings;
+pub mod transport;
+pub mod tunnel;
+
 pub struct Record {
     pub alias: String,
     pub username: String,
@@ -27,95 +45,317 @@ fn home_dir() -> Result<PathBuf, ()> {
     })
 }
 
+/// Expands a leading `~` to the home directory, as ssh does for paths in its config.
+fn expand_home(path: &str) -> Result<PathBuf, ()> {
+    match path.strip_prefix("~/").or_else(|| path.strip_prefix("~\\")) {
+        Some(rest) => Ok(home_dir()?.join(rest)),
+        None if path == "~" => home_dir(),
+        None => Ok(PathBuf::from(path)),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Block {
+    /// The options before the first `Host` or `Match`, which apply to every connection.
+    Global,
+    Host,
+    /// A `Match` block, whose criteria are kept in `host`.
+    Match,
+}
+
+/// A line of a block below its `Host` or `Match` line.
+#[derive(Clone)]
+struct Line {
+    /// The keyword of an option; empty for comments, blank lines, sshh's metadata
+    /// and lines without a value.
+    keyword: String,
+    value: String,
+    /// The line as read from the ssh config, see [`render_line`]; `None` for a new option.
+    text: Option<String>,
+}
+
+impl Line {
+    fn read(keyword: &str, value: &str, text: &str) -> Self {
+        Line {
+            keyword: keyword.to_string(),
+            value: value.to_string(),
+            text: Some(text.to_string()),
+        }
+    }
+
+    fn is_comment(&self) -> bool {
+        self.keyword.is_empty() && self.text.as_deref().is_some_and(|x| x.trim_start().starts_with('#'))
+    }
+
+    fn is_meta(&self) -> bool {
+        self.keyword.is_empty() && self.text.as_deref().is_some_and(|x| x.trim_start().starts_with(META_PREFIX))
+    }
+}
+
+/// The options of a host block which sshh keeps as fields of its [`Entry`].
+const FIELDS: [&str; 3] = ["HostName", "User", "Port"];
+
+#[derive(Clone)]
 struct Entry {
+    block: Block,
     host: String,
     hostname: String,
     user: String,
-    port: u16,
+    port: Option<u16>,
+    meta: Vec<(String, String)>,
+    /// The metadata as read; its lines are written back as they were while it's unchanged.
+    read_meta: Vec<(String, String)>,
+    /// The comment lines directly above the `Host` or `Match` line, which belong to the block.
+    comments: Vec<String>,
+    /// The `Host` or `Match` line as read.
+    header: Option<String>,
+    /// The other lines of the block, in order. The first `HostName`, `User` and
+    /// `Port` of a host block hold the fields above, see [`Entry::is_field`].
+    lines: Vec<Line>,
+    /// Whether the entry comes from the system-wide ssh config, which sshh never writes.
+    system: bool,
 }
 
-fn parse_entries(content: &str) -> Result<Vec<Entry>, ()> {
-    let mut result = Vec::new();
-    macro_rules! check_and_push {
-        ($entry:ident) => {
-            if $entry.hostname.is_empty() {
-                warn!(
-                    "ssh config contains host '{}' which has no hostname",
-                    $entry.host
-                );
-            } else if $entry.user.is_empty() {
-                warn!(
-                    "ssh config contains host '{}' which has no user",
-                    $entry.host
-                );
-            } else {
-                result.push($entry);
-            }
-        };
+impl Entry {
+    fn new(block: Block, host: &str) -> Self {
+        Entry {
+            block,
+            host: host.to_string(),
+            hostname: String::new(),
+            user: String::new(),
+            port: None,
+            meta: Vec::new(),
+            read_meta: Vec::new(),
+            comments: Vec::new(),
+            header: None,
+            lines: Vec::new(),
+            system: false,
+        }
+    }
+
+    fn port(&self) -> u16 {
+        self.port.unwrap_or(22)
     }
 
-    enum State {
-        Initial,
-        Collecting(Entry),
+    /// Whether this is the `Host` block of `alias`.
+    fn has_alias(&self, alias: &str) -> bool {
+        self.block == Block::Host && self.host == alias
     }
-    let mut state = State::Initial;
-    for (i, line) in content.lines().enumerate() {
-        let words: Vec<&str> = line.split_whitespace().collect();
-        if words.len() < 2 {
-            continue; // invalid line
+
+    /// Whether the block may apply to several hosts, like `Host *` or `Match`.
+    fn is_wildcard(&self) -> bool {
+        match self.block {
+            Block::Global => false,
+            Block::Host => self.host.contains(['*', '?', '!']),
+            Block::Match => true,
         }
-        let word0 = words[0].to_lowercase();
-        match state {
-            State::Initial => {
-                if word0 != "host" {
-                    continue;
-                }
-                let entry = Entry {
-                    host: words[1].to_string(),
-                    hostname: String::new(),
-                    user: String::new(),
-                    port: 22,
+    }
+
+    /// Whether the block is an alias sshh lists and connects to.
+    fn is_listed(&self) -> bool {
+        self.block == Block::Host
+            && !self.is_wildcard()
+            && !self.hostname.is_empty()
+            && !self.user.is_empty()
+    }
+
+    /// Whether the line at `index` holds a field, being the first `HostName`,
+    /// `User` or `Port` of a host block, which is the one ssh uses.
+    fn is_field(&self, index: usize) -> bool {
+        let keyword = &self.lines[index].keyword;
+        self.block == Block::Host
+            && FIELDS.iter().any(|x| x.eq_ignore_ascii_case(keyword))
+            && !self.lines[..index]
+                .iter()
+                .any(|x| x.keyword.eq_ignore_ascii_case(keyword))
+    }
+
+    /// Returns the option lines of the block, without the fields.
+    fn options(&self) -> impl Iterator<Item = &Line> {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(|(i, x)| !x.keyword.is_empty() && !self.is_field(*i))
+            .map(|(_, x)| x)
+    }
+
+    fn option(&self, keyword: &str) -> Option<&str> {
+        self.options()
+            .find(|x| x.keyword.eq_ignore_ascii_case(keyword))
+            .map(|x| x.value.as_str())
+    }
+
+    fn set_option(&mut self, keyword: &str, value: Option<String>) {
+        let index = (0..self.lines.len())
+            .find(|i| self.lines[*i].keyword.eq_ignore_ascii_case(keyword) && !self.is_field(*i));
+        match (index, value) {
+            (Some(i), Some(value)) => self.lines[i].value = value,
+            (Some(i), None) => {
+                self.lines.remove(i);
+            }
+            (None, Some(value)) => {
+                // Below the last option, above the trailing comments and blank lines.
+                let end = self
+                    .lines
+                    .iter()
+                    .rposition(|x| !x.keyword.is_empty())
+                    .map_or(0, |x| x + 1);
+                let line = Line {
+                    keyword: keyword.to_string(),
+                    value,
+                    text: None,
                 };
-                state = State::Collecting(entry);
+                self.lines.insert(end, line);
             }
-            State::Collecting(ref mut entry) => match &word0 {
-                x if x == "host" => {
-                    let mut entry1 = Entry {
-                        host: words[1].to_string(),
-                        hostname: String::new(),
-                        user: String::new(),
-                        port: 22,
-                    };
-                    std::mem::swap(entry, &mut entry1);
-                    check_and_push!(entry1);
-                }
-                x if x == "hostname" => entry.hostname = words[1].to_string(),
-                x if x == "user" => entry.user = words[1].to_string(),
-                x if x == "port" => {
-                    entry.port = words[1].parse().map_err(|_| {
-                        error!("ssh config contains invalid port option at line {}", i);
-                    })?
-                }
-                _ => continue,
-            },
+            (None, None) => {}
+        }
+    }
+
+    /// Returns the indentation of the options as written, if any were read.
+    fn indent(&self) -> Option<&str> {
+        self.lines
+            .iter()
+            .filter(|x| !x.keyword.is_empty())
+            .find_map(|x| x.text.as_deref())
+            .map(|x| &x[..x.len() - x.trim_start().len()])
+    }
+
+    fn proxy_jump(&self) -> Vec<&str> {
+        match self.option("ProxyJump") {
+            Some(x) if !x.eq_ignore_ascii_case("none") => x.split(',').collect(),
+            _ => Vec::new(),
         }
     }
-    if let State::Collecting(entry) = state {
-        check_and_push!(entry);
+
+    fn meta(&self, key: &str) -> Option<&str> {
+        self.meta
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn set_meta(&mut self, key: &str, value: Option<String>) {
+        self.meta.retain(|(k, _)| k != key);
+        if let Some(value) = value {
+            self.meta.push((key.to_string(), value));
+        }
+    }
+
+    /// Whether the entry runs a command on connect, set with `--cd` or `--run`.
+    fn has_startup_command(&self) -> bool {
+        self.meta("cd").is_some() || self.meta("run").is_some()
+    }
+
+    fn tags(&self) -> Vec<&str> {
+        self.meta("tags")
+            .map(|x| x.split(',').filter(|x| !x.is_empty()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether the entry has expired at `now`, see [`expiry::now`].
+    fn is_expired(&self, now: u64) -> bool {
+        self.meta("expires")
+            .and_then(expiry::parse_timestamp)
+            .is_some_and(|x| x <= now)
     }
 
+    fn to_record(&self) -> Record {
+        Record {
+            alias: self.host.clone(),
+            username: self.user.clone(),
+            host: self.hostname.clone(),
+            port: self.port(),
+        }
+    }
+}
+
+/// Prefix of the comment lines which carry sshh's own metadata inside a host block.
+const META_PREFIX: &str = "# sshh:";
+
+/// Splits `Keyword value` or `Keyword=value`.
+pub(crate) fn split_option(line: &str) -> Option<(&str, &str)> {
+    let end = line.find(|x: char| x.is_whitespace() || x == '=')?;
+    let keyword = &line[..end];
+    let value = line[end..].trim_start();
+    let value = value.strip_prefix('=').unwrap_or(value).trim();
+    Some((keyword, value))
+}
+
+fn parse_entries(content: &str) -> Result<Vec<Entry>, ()> {
+    let mut result = vec![Entry::new(Block::Global, "")];
+    for (i, text) in content.lines().enumerate() {
+        let entry = result.last_mut().unwrap();
+        let line = text.trim();
+        if entry.block == Block::Host {
+            if let Some(meta) = line.strip_prefix(META_PREFIX) {
+                if let Some((k, v)) = meta.trim().split_once('=') {
+                    entry.set_meta(k.trim(), Some(v.trim().to_string()));
+                }
+                entry.lines.push(Line::read("", "", text));
+                continue;
+            }
+        }
+        // Comments, blank lines and lines without a value are kept verbatim.
+        let split = split_option(line).filter(|(_, v)| !line.starts_with('#') && !v.is_empty());
+        let Some((keyword, value)) = split else {
+            entry.lines.push(Line::read("", "", text));
+            continue;
+        };
+        // Inside Match, these apply conditionally and are kept as plain options.
+        // ssh uses the first value of an option, so repeated ones are kept as they are.
+        let host = entry.block == Block::Host;
+        let block = match keyword.to_lowercase().as_str() {
+            "host" => Block::Host,
+            "match" => Block::Match,
+            "hostname" if host && entry.hostname.is_empty() => {
+                entry.hostname = value.to_string();
+                entry.lines.push(Line::read(keyword, value, text));
+                continue;
+            }
+            "user" if host && entry.user.is_empty() => {
+                entry.user = value.to_string();
+                entry.lines.push(Line::read(keyword, value, text));
+                continue;
+            }
+            "port" if host && entry.port.is_none() => {
+                entry.port = Some(value.parse().map_err(|_| {
+                    error!("ssh config contains invalid port option at line {}", i + 1);
+                })?);
+                entry.lines.push(Line::read(keyword, value, text));
+                continue;
+            }
+            _ => {
+                entry.lines.push(Line::read(keyword, value, text));
+                continue;
+            }
+        };
+        // The comments right above a block describe it, and move along with it.
+        let start = entry
+            .lines
+            .iter()
+            .rposition(|x| !x.is_comment() || x.is_meta())
+            .map_or(0, |x| x + 1);
+        let comments = entry.lines.split_off(start);
+        let mut next = Entry::new(block, value);
+        next.comments = comments.into_iter().filter_map(|x| x.text).collect();
+        next.header = Some(text.to_string());
+        result.push(next);
+    }
+    for entry in &mut result {
+        entry.read_meta = entry.meta.clone();
+    }
     Ok(result)
 }
 
 fn open_and_load_config(create: bool, write: bool) -> Result<Option<(File, Vec<Entry>)>, ()> {
     let parent_dir = home_dir()?.join(".ssh");
     if create {
-        std::fs::create_dir_all(&parent_dir).map_err(|e| {
+        perms::create_dir(&parent_dir).map_err(|e| {
             error!("failed to create ssh directory: {}", e);
         })?;
     }
     let path = parent_dir.join("config");
-    let mut file = match File::options()
+    let mut file = match perms::private_file(&mut File::options())
         .create(create)
         .write(write)
         .read(true)
@@ -134,107 +374,801 @@ fn open_and_load_config(create: bool, write: bool) -> Result<Option<(File, Vec<E
     file.read_to_string(&mut content).map_err(|e| {
         error!("failed to read ssh config file: {}", e);
     })?;
+    perms::check_permissions()?;
     let entries = parse_entries(&content)?;
     Ok(Some((file, entries)))
 }
 
 pub fn list_entries() -> Result<(), ()> {
-    let result = open_and_load_config(false, false)?;
-    if result.is_none() {
+    let entries = load_entries()?;
+    if entries.is_empty() {
         warn!("no entries");
         return Ok(());
     }
-    let (_, entries) = result.unwrap();
+    let now = expiry::now();
+    let mut expired = 0;
     for entry in entries {
-        custom!(title=entry.host; "{}@{}:{}", entry.user, entry.hostname, entry.port);
+        if entry.block != Block::Host || entry.is_wildcard() {
+            continue;
+        }
+        if entry.is_expired(now) {
+            expired += 1;
+            continue;
+        }
+        if entry.hostname.is_empty() {
+            warn!("ssh config contains host '{}' which has no hostname", entry.host);
+            continue;
+        }
+        if entry.user.is_empty() {
+            warn!("ssh config contains host '{}' which has no user", entry.host);
+            continue;
+        }
+        let mut line = format!("{}@{}:{}", entry.user, entry.hostname, entry.port());
+        let tags = entry.tags();
+        if !tags.is_empty() {
+            line += &format!(" [{}]", tags.join(","));
+        }
+        if entry.system {
+            line += " (system)";
+        }
+        custom!(title=entry.host; "{}", line);
+    }
+    if expired > 0 {
+        warn!("{} expired entries hidden, run 'sshh host prune' to remove them", expired);
     }
     Ok(())
 }
 
-macro_rules! writeln {
-    ($($tt:tt)+) => {
-        ::std::writeln!($($tt)+).map_err(|e| {
-            error!("failed to write ssh config file: {}", e);
-        })?;
+/// Matches `text` against a ssh_config style pattern, where `*` matches any
+/// sequence of characters and `?` matches exactly one character.
+pub fn match_pattern(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((bp, bt)) = backtrack {
+            p = bp + 1;
+            t = bt + 1;
+            backtrack = Some((bp, bt + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|x| *x == '*')
+}
+
+/// Returns the entries whose alias matches `pattern` and which carry `tag`.
+/// A `None` filter matches everything.
+pub fn select_records(pattern: Option<&str>, tag: Option<&str>) -> Result<Vec<Record>, ()> {
+    let entries = load_entries()?;
+    let now = expiry::now();
+    let records = entries
+        .iter()
+        .filter(|x| x.is_listed() && !x.is_expired(now))
+        .filter(|x| pattern.is_none_or(|p| match_pattern(p, &x.host)))
+        .filter(|x| tag.is_none_or(|t| x.tags().contains(&t)))
+        .map(Entry::to_record)
+        .collect();
+    Ok(records)
+}
+
+/// Returns the entry of `alias`, if any.
+pub fn find_record(alias: &str) -> Result<Option<Record>, ()> {
+    let record = load_entries()?
+        .iter()
+        .find(|x| x.has_alias(alias) && x.is_listed())
+        .map(Entry::to_record);
+    Ok(record)
+}
+
+/// How mutations of the ssh config are applied.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    Write,
+    /// Write, then print the changes as a unified diff.
+    Diff,
+    /// Only print the changes as a unified diff.
+    DryRun,
+}
+
+static WRITE_MODE: AtomicU8 = AtomicU8::new(WriteMode::Write as u8);
+
+pub fn set_write_mode(mode: WriteMode) {
+    WRITE_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+fn write_mode() -> WriteMode {
+    match WRITE_MODE.load(Ordering::Relaxed) {
+        x if x == WriteMode::Diff as u8 => WriteMode::Diff,
+        x if x == WriteMode::DryRun as u8 => WriteMode::DryRun,
+        _ => WriteMode::Write,
+    }
+}
+
+/// Returns `text`, the line as read, if it still has `value`, or else the line with
+/// `value` in place of its own, keeping its indentation and separator. A new line
+/// is written as `<indent><keyword> <value>`.
+fn render_line(text: Option<&str>, indent: &str, keyword: &str, value: &str) -> String {
+    let Some(text) = text else {
+        return format!("{}{} {}", indent, keyword, value);
+    };
+    let start = text.len() - text.trim_start().len();
+    let Some((written, current)) = split_option(&text[start..]) else {
+        return format!("{}{} {}", indent, keyword, value);
     };
+    if current == value {
+        return text.to_string();
+    }
+    let rest = &text[start + written.len()..];
+    let separator = rest.len() - rest.trim_start_matches(|x: char| x.is_whitespace() || x == '=').len();
+    format!("{}{}", &text[..start + written.len() + separator], value)
 }
 
-macro_rules! write_entries {
-    ($file:ident, $entries:ident) => {
-        $file.seek(SeekFrom::Start(0)).map_err(|e| {
+/// Renders the entries back into an ssh config. The lines sshh did not change are
+/// written as they were read.
+fn render_entries(entries: &[Entry]) -> String {
+    // New blocks are indented like the existing ones.
+    let default_indent = entries
+        .iter()
+        .filter(|x| x.block != Block::Global)
+        .find_map(Entry::indent)
+        .unwrap_or("    ");
+    let mut content = String::new();
+    for entry in entries {
+        let mut lines = entry.comments.clone();
+        match entry.block {
+            Block::Global => {}
+            Block::Host => lines.push(render_line(entry.header.as_deref(), "", "Host", &entry.host)),
+            Block::Match => lines.push(render_line(entry.header.as_deref(), "", "Match", &entry.host)),
+        }
+        let indent = match entry.block {
+            Block::Global => "",
+            _ => entry.indent().unwrap_or(default_indent),
+        };
+        let meta_changed = entry.meta != entry.read_meta;
+        if meta_changed {
+            for (k, v) in &entry.meta {
+                lines.push(format!("{}{} {}={}", indent, META_PREFIX, k, v));
+            }
+        }
+        // Fields without a line, e.g. of a new entry, come first.
+        let port = entry.port.map(|x| x.to_string()).unwrap_or_default();
+        let fields = [("HostName", &entry.hostname), ("User", &entry.user), ("Port", &port)];
+        for (name, value) in fields {
+            let written = (0..entry.lines.len())
+                .any(|i| entry.is_field(i) && entry.lines[i].keyword.eq_ignore_ascii_case(name));
+            if !value.is_empty() && !written {
+                lines.push(render_line(None, indent, name, value));
+            }
+        }
+        for (i, line) in entry.lines.iter().enumerate() {
+            let text = line.text.as_deref();
+            if line.keyword.is_empty() {
+                // Metadata which changed was written above.
+                if !(meta_changed && line.is_meta()) {
+                    lines.push(text.unwrap_or_default().to_string());
+                }
+            } else if entry.is_field(i) {
+                let (_, value) = fields
+                    .iter()
+                    .find(|(x, _)| x.eq_ignore_ascii_case(&line.keyword))
+                    .unwrap();
+                if !value.is_empty() {
+                    lines.push(render_line(text, indent, &line.keyword, value));
+                }
+            } else {
+                lines.push(render_line(text, indent, &line.keyword, &line.value));
+            }
+        }
+        for line in lines {
+            content.push_str(&line);
+            content.push('\n');
+        }
+    }
+    content
+}
+
+/// Replaces the content of the opened ssh config file, according to the [`WriteMode`].
+///
+/// The file is rewritten in place, so it keeps its mode and owner.
+fn write_config(file: &mut File, content: &str) -> Result<(), ()> {
+    let mode = write_mode();
+    if mode != WriteMode::Write {
+        file.seek(SeekFrom::Start(0)).map_err(|e| {
             error!("failed to seek to the start of ssh config file: {}", e);
         })?;
-        $file.set_len(0).map_err(|e| {
-            error!("failed to truncate ssh config file: {}", e);
+        let mut old = String::new();
+        file.read_to_string(&mut old).map_err(|e| {
+            error!("failed to read ssh config file: {}", e);
         })?;
-        for entry in $entries {
-            writeln!($file, "Host {}", entry.host);
-            writeln!($file, "    HostName {}", entry.hostname);
-            writeln!($file, "    User {}", entry.user);
-            writeln!($file, "    Port {}", entry.port);
+        print_diff("~/.ssh/config", &old, content);
+        if mode == WriteMode::DryRun {
+            return Ok(());
+        }
+    }
+
+    file.seek(SeekFrom::Start(0)).map_err(|e| {
+        error!("failed to seek to the start of ssh config file: {}", e);
+    })?;
+    file.set_len(0).map_err(|e| {
+        error!("failed to truncate ssh config file: {}", e);
+    })?;
+    file.write_all(content.as_bytes()).map_err(|e| {
+        error!("failed to write ssh config file: {}", e);
+    })
+}
+
+fn print_diff(name: &str, old: &str, new: &str) {
+    let diff = diff::unified_diff(old, new, name);
+    if diff.is_empty() {
+        warn!("no changes");
+    } else {
+        print!("{}", diff);
+    }
+}
+
+macro_rules! write_entries {
+    ($file:ident, $entries:ident) => {
+        write_config(&mut $file, &render_entries(&$entries))?;
+    };
+}
+
+/// Asks a yes/no question on the terminal; anything but 'y' means no.
+fn confirm(prompt: &str) -> bool {
+    if !std::io::stdin().is_terminal() {
+        warn!("{} skipped, stdin is not a terminal", prompt);
+        return false;
+    }
+    eprint!("{} [y/N] ", prompt);
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/// Loads the entries of the ssh config, or nothing if the file does not exist,
+/// followed by the system-wide entries it doesn't override.
+fn load_entries() -> Result<Vec<Entry>, ()> {
+    let result = open_and_load_config(false, false)?;
+    let mut entries = result.map(|(_, entries)| entries).unwrap_or_default();
+    add_system_entries(&mut entries);
+    Ok(entries)
+}
+
+/// Adds the system-wide entries which the user's `entries` don't override.
+fn add_system_entries(entries: &mut Vec<Entry>) {
+    for entry in load_system_entries() {
+        if !entries.iter().any(|x| x.has_alias(&entry.host)) {
+            entries.push(entry);
         }
+    }
+}
+
+#[cfg(windows)]
+fn system_config_dir() -> Option<PathBuf> {
+    std::env::var("PROGRAMDATA")
+        .ok()
+        .map(|x| PathBuf::from(x).join("ssh"))
+}
+
+#[cfg(unix)]
+fn system_config_dir() -> Option<PathBuf> {
+    Some(PathBuf::from("/etc/ssh"))
+}
+
+/// Loads the host entries of the system-wide ssh config: the `ssh_config.d/*.conf`
+/// files in name order, which the stock `ssh_config` includes at its top, then
+/// `ssh_config` itself. Only the first block of an alias counts, as in ssh.
+fn load_system_entries() -> Vec<Entry> {
+    let Some(dir) = system_config_dir() else {
+        return Vec::new();
     };
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir.join("ssh_config.d"))
+        .map(|x| {
+            x.flatten()
+                .map(|x| x.path())
+                .filter(|x| x.extension().is_some_and(|x| x == "conf"))
+                .collect()
+        })
+        .unwrap_or_default();
+    paths.sort();
+    paths.push(dir.join("ssh_config"));
+
+    let mut result: Vec<Entry> = Vec::new();
+    for path in paths {
+        let content = match std::fs::read_to_string(&path) {
+            Ok(x) => x,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => {
+                warn!("failed to read '{}': {}", path.display(), e);
+                continue;
+            }
+        };
+        let Ok(entries) = parse_entries(&content) else {
+            continue;
+        };
+        for mut entry in entries {
+            if entry.is_listed() && !result.iter().any(|x| x.has_alias(&entry.host)) {
+                entry.system = true;
+                result.push(entry);
+            }
+        }
+    }
+    result
 }
 
-fn parse_uri(uri: &str) -> Result<(&str, &str, u16), ()> {
-    let mut iter = uri.splitn(2, '@');
-    let user = iter.next().ok_or_else(|| {
-        error!("invalid uri '{}', no username part", uri);
-    })?;
+/// Inserts a new host entry above the catch-all blocks, as ssh uses the first
+/// value it obtains, and returns its index.
+fn insert_entry(entries: &mut Vec<Entry>, entry: Entry) -> usize {
+    let index = entries
+        .iter()
+        .position(Entry::is_wildcard)
+        .unwrap_or(entries.len());
+    entries.insert(index, entry);
+    index
+}
+
+/// Copies the system-wide entry of `alias` into the user's `entries` unless they
+/// have one already, so that a mutation applies to a user-level override.
+pub(crate) fn override_system_entry(entries: &mut Vec<Entry>, alias: &str) {
+    if entries.iter().any(|x| x.has_alias(alias)) {
+        return;
+    }
+    let Some(mut entry) = load_system_entries().into_iter().find(|x| x.has_alias(alias)) else {
+        return;
+    };
+    warn!("'{}' is provided by the system ssh config, adding a user-level override", alias);
+    entry.system = false;
+    insert_entry(entries, entry);
+}
+
+/// Reports that `alias` is missing from the user's ssh config.
+fn report_not_found(alias: &str) {
+    if load_system_entries().iter().any(|x| x.has_alias(alias)) {
+        error!("entry '{}' is provided by the system ssh config, which is read-only", alias);
+    } else {
+        error!("entry '{}' not found", alias);
+    }
+}
 
-    let remaining = iter.next().ok_or_else(|| {
-        error!("invalid uri '{}', no host and port part", uri);
+/// Loads the entries of the ssh config, lets `f` modify them and writes them back.
+fn update_entries<F>(f: F) -> Result<(), ()>
+where
+    F: FnOnce(&mut Vec<Entry>) -> Result<(), ()>,
+{
+    let result = open_and_load_config(false, true)?;
+    let (mut file, mut entries) = result.ok_or_else(|| {
+        error!("no entries");
     })?;
-    let mut iter = remaining.rsplitn(2, ':');
-    let hostname = iter.next().ok_or_else(|| {
-        error!("invalid uri '{}', no hostname part", uri);
+    f(&mut entries)?;
+    write_entries!(file, entries);
+    Ok(())
+}
+
+/// Returns the directory where sshh keeps its runtime data.
+#[cfg(windows)]
+fn data_dir() -> Result<PathBuf, ()> {
+    std::env::var("LOCALAPPDATA")
+        .map(|x| PathBuf::from(x).join("sshh"))
+        .map_err(|_| {
+            error!("failed to get env var 'LOCALAPPDATA'");
+        })
+}
+
+/// Returns the directory where sshh keeps its runtime data.
+#[cfg(unix)]
+fn data_dir() -> Result<PathBuf, ()> {
+    match std::env::var("XDG_DATA_HOME") {
+        Ok(x) if !x.is_empty() => Ok(PathBuf::from(x).join("sshh")),
+        _ => Ok(home_dir()?.join(".local").join("share").join("sshh")),
+    }
+}
+
+/// Quotes `s` for a remote POSIX shell, leaving a leading `~/` to be expanded.
+pub(crate) fn shell_quote(s: &str) -> String {
+    let (home, rest) = match s.strip_prefix("~/") {
+        Some(rest) => ("~/", rest),
+        None => ("", s),
+    };
+    format!("{}'{}'", home, rest.replace('\'', r"'\''"))
+}
+
+/// Splits `[ssh://]user@host[:port]` into its parts; IPv6 addresses must be
+/// enclosed in brackets, which are removed.
+pub(crate) fn split_uri(uri: &str) -> Result<(&str, &str, Option<u16>), ()> {
+    let (user, hostname, port) = split_endpoint(uri)?;
+    let user = user.ok_or_else(|| {
+        error!("invalid uri '{}', no username part", uri);
     })?;
-    if hostname.contains(':') {
-        if !hostname.starts_with('[') || !hostname.ends_with(']') {
+    Ok((user, hostname, port))
+}
+
+/// Like [`split_uri`], but the username is optional.
+fn split_endpoint(uri: &str) -> Result<(Option<&str>, &str, Option<u16>), ()> {
+    let rest = uri.strip_prefix("ssh://").unwrap_or(uri);
+    let rest = rest.strip_suffix('/').unwrap_or(rest);
+    let (user, remaining) = match rest.split_once('@') {
+        Some((user, remaining)) => (Some(user), remaining),
+        None => (None, rest),
+    };
+    if user == Some("") {
+        error!("invalid uri '{}', no username part", uri);
+        return Err(());
+    }
+
+    let (hostname, port) = if let Some(remaining) = remaining.strip_prefix('[') {
+        let (hostname, port) = remaining.split_once(']').ok_or_else(|| {
+            error!("invalid uri '{}', ipv6 address is not closed by ']'", uri);
+        })?;
+        match port {
+            "" => (hostname, None),
+            x => (hostname, Some(x.strip_prefix(':').unwrap_or("invalid"))),
+        }
+    } else {
+        if remaining.matches(':').count() > 1 {
             error!(
-                "invalid uri '{}', hostname contains ':' but is not a valid ipv6 address",
+                "invalid uri '{}', hostname contains ':' but is not enclosed in '[]'",
                 uri
             );
             return Err(());
         }
+        match remaining.split_once(':') {
+            Some((hostname, port)) => (hostname, Some(port)),
+            None => (remaining, None),
+        }
+    };
+    if hostname.is_empty() {
+        error!("invalid uri '{}', no hostname part", uri);
+        return Err(());
     }
 
-    let port = iter
-        .next()
-        .map(|x| {
-            x.parse().map_err(|_| {
-                error!("invalid uri '{}', port part is not a valid number", uri);
-            })
-        })
-        .unwrap_or(Ok(22))?;
-
+    let port = match port {
+        Some(x) => Some(x.parse().map_err(|_| {
+            error!("invalid uri '{}', port part is not a valid number", uri);
+        })?),
+        None => None,
+    };
     Ok((user, hostname, port))
 }
 
-pub fn add_or_modify_entry(host: &str, uri: &str) -> Result<(), ()> {
-    let (user, hostname, port) = parse_uri(uri)?;
-    let (mut file, mut entries) = open_and_load_config(true, true)?.ok_or_else(|| {
-        error!("unexpected: ssh config file not opened for writing");
+/// Optional settings applied by [`add_or_modify_entry`]; `None` keeps the current value.
+#[derive(Default)]
+pub struct EntryOptions {
+    pub tags: Option<Vec<String>>,
+    /// The jump hosts to connect through, in order; an empty list removes them.
+    pub via: Option<Vec<String>>,
+    /// How long the entry stays listed from now on; zero removes the expiry.
+    pub ttl: Option<Duration>,
+    /// The template to apply instead of the one the entry was created from, or
+    /// the first one matching the hostname.
+    pub template: Option<String>,
+    /// The remote directory to change to on connect; an empty string removes it.
+    pub cd: Option<String>,
+    /// The remote command to run on connect; an empty string removes it.
+    pub run: Option<String>,
+}
+
+/// Adds or updates the entry of `host`. The username may be omitted from `uri`
+/// if the applied template provides one.
+pub fn add_or_modify_entry(host: &str, uri: &str, options: &EntryOptions) -> Result<(), ()> {
+    let (user, hostname, port) = split_endpoint(uri)?;
+    let settings = load_settings()?;
+    // A dry run writes nothing, not even the ssh directory or an empty config.
+    let dry_run = write_mode() == WriteMode::DryRun;
+    let (file, mut entries) = match open_and_load_config(!dry_run, !dry_run)? {
+        Some((file, entries)) => (Some(file), entries),
+        None if dry_run => (None, parse_entries("")?),
+        None => {
+            error!("unexpected: ssh config file not opened for writing");
+            return Err(());
+        }
+    };
+    override_system_entry(&mut entries, host);
+    if let Some(via) = &options.via {
+        // The hops may come from the system-wide ssh config too.
+        let mut known = entries.clone();
+        add_system_entries(&mut known);
+        check_jump_hosts(&known, host, via)?;
+    }
+    // ssh matches aliases case-insensitively, so 'Web' next to 'web' would never be used.
+    if let Some(other) = entries
+        .iter()
+        .find(|x| x.block == Block::Host && x.host != host && x.host.eq_ignore_ascii_case(host))
+    {
+        error!("entry '{}' already exists, aliases differing in case are the same to ssh", other.host);
+        return Err(());
+    }
+    let index = match entries.iter().position(|x| x.has_alias(host)) {
+        Some(i) => i,
+        None => insert_entry(&mut entries, Entry::new(Block::Host, host)),
+    };
+    let entry = &mut entries[index];
+    let previous = (!entry.hostname.is_empty()).then(|| (entry.hostname.clone(), entry.port()));
+
+    let template = match (&options.template, entry.meta("template")) {
+        (Some(name), _) => Some(settings.template(name).ok_or_else(|| {
+            error!("template '{}' not found", name);
+        })?),
+        (None, Some(name)) => {
+            let template = settings.template(name);
+            if template.is_none() {
+                warn!("template '{}' of '{}' not found", name, host);
+            }
+            template
+        }
+        (None, None) => settings.find_template(hostname),
+    };
+    entry.hostname = hostname.to_string();
+    if let Some(user) = user {
+        entry.user = user.to_string();
+    }
+    // An entry read without a port keeps using the default one without writing it.
+    entry.port = match port {
+        None if entry.header.is_some() && entry.port.is_none() => None,
+        _ => Some(port.unwrap_or(22)),
+    };
+    if let Some(template) = template {
+        apply_template(entry, template, user.is_some(), port.is_some())?;
+    }
+    if entry.user.is_empty() {
+        error!("invalid uri '{}', no username part and no template provides one", uri);
+        return Err(());
+    }
+    apply_options(entry, options)?;
+
+    let stale = previous.filter(|(hostname, port)| *hostname != entry.hostname || *port != entry.port());
+    match file {
+        Some(mut file) => {
+            write_entries!(file, entries);
+        }
+        None => print_diff("~/.ssh/config", "", &render_entries(&entries)),
+    }
+    if let Some((hostname, port)) = stale {
+        known_hosts::offer_to_remove_keys(&hostname, port)?;
+    }
+    Ok(())
+}
+
+/// Returns the options set by the template the entry was created from.
+fn template_keys(entry: &Entry) -> Vec<&str> {
+    entry
+        .meta("template.keys")
+        .map(|x| x.split(',').filter(|x| !x.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+/// Sets the options of `template` on `entry`, except `User` and `Port` if they
+/// were given explicitly. The options which an earlier version of the template
+/// set and the current one lacks are removed; `User` and `Port` stay.
+fn apply_template(
+    entry: &mut Entry,
+    template: &Template,
+    explicit_user: bool,
+    explicit_port: bool,
+) -> Result<(), ()> {
+    let removed: Vec<String> = template_keys(entry)
+        .into_iter()
+        .filter(|x| !x.eq_ignore_ascii_case("user") && !x.eq_ignore_ascii_case("port"))
+        .filter(|x| !template.options.iter().any(|(k, _)| k.eq_ignore_ascii_case(x)))
+        .map(str::to_string)
+        .collect();
+    for keyword in removed {
+        entry.set_option(&keyword, None);
+    }
+
+    let mut keys = Vec::new();
+    for (keyword, value) in &template.options {
+        match keyword.to_lowercase().as_str() {
+            "hostname" => continue, // always the one of the uri
+            "user" if explicit_user => continue,
+            "user" => entry.user = value.clone(),
+            "port" if explicit_port => continue,
+            "port" => {
+                entry.port = Some(value.parse().map_err(|_| {
+                    error!("template '{}' contains invalid port '{}'", template.name, value);
+                })?)
+            }
+            _ => entry.set_option(keyword, Some(value.clone())),
+        }
+        keys.push(keyword.as_str());
+    }
+    entry.set_meta("template", Some(template.name.clone()));
+    entry.set_meta("template.keys", if keys.is_empty() { None } else { Some(keys.join(",")) });
+    Ok(())
+}
+
+/// Applies the current version of their template to the entries created from one,
+/// or only to `alias`.
+pub fn reapply_templates(alias: Option<&str>) -> Result<(), ()> {
+    let settings = load_settings()?;
+    let mut count = 0;
+    update_entries(|entries| {
+        let mut found = false;
+        for entry in entries.iter_mut() {
+            if alias.is_some_and(|x| !entry.has_alias(x)) {
+                continue;
+            }
+            found = true;
+            let Some(name) = entry.meta("template").map(str::to_string) else {
+                if alias.is_some() {
+                    error!("entry '{}' was not created from a template", entry.host);
+                    return Err(());
+                }
+                continue;
+            };
+            let Some(template) = settings.template(&name) else {
+                warn!("template '{}' of '{}' not found", name, entry.host);
+                continue;
+            };
+            let keys = template_keys(entry);
+            let explicit_user = !entry.user.is_empty() && !keys.iter().any(|x| x.eq_ignore_ascii_case("user"));
+            let explicit_port = entry.port.is_some() && !keys.iter().any(|x| x.eq_ignore_ascii_case("port"));
+            apply_template(entry, template, explicit_user, explicit_port)?;
+            count += 1;
+        }
+        if let (Some(alias), false) = (alias, found) {
+            report_not_found(alias);
+            return Err(());
+        }
+        Ok(())
     })?;
-    let mut modified = false;
-    for entry in &mut entries {
-        if entry.host == host {
-            modified = true;
-            entry.hostname = hostname.to_string();
-            entry.user = user.to_string();
-            entry.port = port;
+    match alias {
+        Some(alias) if count > 0 => custom!(title=alias; "template reapplied"),
+        None if count > 0 => custom!(title="template"; "reapplied to {} entries", count),
+        _ => warn!("no entries created from a template"),
+    }
+    Ok(())
+}
+
+fn apply_options(entry: &mut Entry, options: &EntryOptions) -> Result<(), ()> {
+    if let Some(tags) = &options.tags {
+        let tags = tags.join(",");
+        entry.set_meta("tags", if tags.is_empty() { None } else { Some(tags) });
+    }
+    if let Some(via) = &options.via {
+        let via = via.join(",");
+        entry.set_option("ProxyJump", if via.is_empty() { None } else { Some(via) });
+    }
+    if let Some(ttl) = options.ttl {
+        let expires = if ttl.is_zero() {
+            None
+        } else {
+            let expires = expiry::now().checked_add(ttl.as_secs()).ok_or_else(|| {
+                error!("invalid ttl, the expiry time is out of range");
+            })?;
+            Some(expiry::format_timestamp(expires))
+        };
+        entry.set_meta("expires", expires);
+    }
+    if options.cd.is_some() || options.run.is_some() {
+        for (key, value) in [("cd", &options.cd), ("run", &options.run)] {
+            if let Some(value) = value {
+                entry.set_meta(key, Some(value.clone()).filter(|x| !x.is_empty()));
+            }
         }
+        update_remote_command(entry);
     }
-    if !modified {
-        entries.push(Entry {
-            host: host.to_string(),
-            hostname: hostname.to_string(),
-            user: user.to_string(),
-            port,
-        });
-        entries.sort_by(|a, b| a.host.cmp(&b.host));
+    Ok(())
+}
+
+/// Derives `RemoteCommand` and `RequestTTY` from the `cd` and `run` metadata.
+fn update_remote_command(entry: &mut Entry) {
+    let command = match (entry.meta("cd"), entry.meta("run")) {
+        (None, None) => None,
+        (Some(dir), None) => Some(format!("cd {} && exec \"$SHELL\" -l", shell_quote(dir))),
+        (None, Some(run)) => Some(run.to_string()),
+        (Some(dir), Some(run)) => Some(format!("cd {} && {}", shell_quote(dir), run)),
+    };
+    // ssh expands '%' tokens in RemoteCommand.
+    let command = command.map(|x| x.replace('%', "%%"));
+    let tty = command.as_ref().map(|_| "yes".to_string());
+    entry.set_option("RemoteCommand", command);
+    entry.set_option("RequestTTY", tty);
+}
+
+/// Checks that every hop is a known alias and that jumping through them never leads back to `host`.
+fn check_jump_hosts(entries: &[Entry], host: &str, via: &[String]) -> Result<(), ()> {
+    for (i, hop) in via.iter().enumerate() {
+        if hop == host {
+            error!("entry '{}' can not jump through itself", host);
+            return Err(());
+        }
+        if via[..i].contains(hop) {
+            error!("jump host '{}' is listed more than once", hop);
+            return Err(());
+        }
+        if !entries.iter().any(|x| x.has_alias(hop)) {
+            error!("jump host '{}' is not a known alias", hop);
+            return Err(());
+        }
+    }
+
+    fn visit<'a>(entries: &'a [Entry], host: &str, path: &mut Vec<&'a str>) -> Result<(), ()> {
+        let current = *path.last().unwrap();
+        let Some(entry) = entries.iter().find(|x| x.has_alias(current)) else {
+            return Ok(()); // not an alias, e.g. 'user@host:port'
+        };
+        for hop in entry.proxy_jump() {
+            if hop == host {
+                error!(
+                    "jump hosts form a cycle: {} -> {} -> {}",
+                    host,
+                    path.join(" -> "),
+                    host
+                );
+                return Err(());
+            }
+            if path.contains(&hop) {
+                continue; // an existing cycle, which doesn't involve the host
+            }
+            path.push(hop);
+            visit(entries, host, path)?;
+            path.pop();
+        }
+        Ok(())
+    }
+    for hop in via {
+        visit(entries, host, &mut vec![hop.as_str()])?;
+    }
+    Ok(())
+}
+
+/// Resolves the hosts ssh connects through to reach `alias`, in connection order.
+///
+/// The first jump host is reached with its own `ProxyJump`, the following ones
+/// through the previous hops, as `ssh -J` does.
+fn resolve_jump_chain<'a>(entries: &'a [Entry], alias: &'a str, chain: &mut Vec<&'a str>) {
+    let Some(entry) = entries.iter().find(|x| x.has_alias(alias)) else {
+        return;
+    };
+    for (i, hop) in entry.proxy_jump().into_iter().enumerate() {
+        if chain.contains(&hop) || hop == alias {
+            continue; // broken config, don't loop forever
+        }
+        if i == 0 {
+            resolve_jump_chain(entries, hop, chain);
+        }
+        chain.push(hop);
+    }
+}
+
+pub fn show_entry(alias: &str) -> Result<(), ()> {
+    let entries = load_entries()?;
+    let entry = entries.iter().find(|x| x.has_alias(alias)).ok_or_else(|| {
+        error!("entry '{}' not found", alias);
+    })?;
+    let system = if entry.system { " (system)" } else { "" };
+    let expired = if entry.is_expired(expiry::now()) { " (expired)" } else { "" };
+    custom!(title=entry.host; "{}@{}:{}{}{}", entry.user, entry.hostname, entry.port(), system, expired);
+    for (k, v) in &entry.meta {
+        custom!(title=k; "{}", v);
+    }
+    for line in entry.options() {
+        custom!(title=line.keyword; "{}", line.value);
+    }
+
+    let mut chain = Vec::new();
+    resolve_jump_chain(&entries, alias, &mut chain);
+    if chain.is_empty() {
+        return Ok(());
+    }
+    custom!(title="chain"; "localhost");
+    for (i, hop) in chain.iter().chain(std::iter::once(&alias)).enumerate() {
+        let endpoint = match entries.iter().find(|x| x.has_alias(hop)) {
+            Some(x) => format!("{}@{}:{}", x.user, x.hostname, x.port()),
+            None => "unknown".to_string(),
+        };
+        custom!(title="chain"; "{}└─ {} ({})", "   ".repeat(i), hop, endpoint);
     }
-    write_entries!(file, entries);
     Ok(())
 }
 
@@ -247,45 +1181,183 @@ pub fn rename_entry(host: &str, new_name: &str) -> Result<(), ()> {
     let (mut file, mut entries) = result.unwrap();
     let mut found = false;
     for entry in &mut entries {
-        if entry.host == host {
+        if entry.has_alias(host) {
             found = true;
             entry.host = new_name.to_string();
             continue;
         }
-        if entry.host == new_name {
-            error!("entry '{}' already exists", new_name);
+        // ssh matches aliases case-insensitively
+        if entry.block == Block::Host && entry.host.eq_ignore_ascii_case(new_name) {
+            error!("entry '{}' already exists", entry.host);
             return Err(());
         }
     }
     if !found {
-        error!("entry '{}' not found", host);
+        report_not_found(host);
         return Err(());
     }
-    entries.sort_by(|a, b| a.host.cmp(&b.host));
+    // Keep the entries jumping through the renamed one working.
+    for entry in &mut entries {
+        let hops = entry.proxy_jump();
+        if hops.contains(&host) {
+            let hops: Vec<&str> = hops
+                .into_iter()
+                .map(|x| if x == host { new_name } else { x })
+                .collect();
+            let hops = hops.join(",");
+            entry.set_option("ProxyJump", Some(hops));
+        }
+    }
     write_entries!(file, entries);
     Ok(())
 }
 
 pub fn remove_entry(host: &str) -> Result<(), ()> {
+    match remove_entries(|x| x.has_alias(host))? {
+        Some(0) => {
+            report_not_found(host);
+            Err(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Removes the expired entries.
+pub fn prune_entries() -> Result<(), ()> {
+    let now = expiry::now();
+    match remove_entries(|x| x.block == Block::Host && x.is_expired(now))? {
+        Some(0) => warn!("no expired entries"),
+        Some(n) => custom!(title="prune"; "{} expired entries removed", n),
+        None => {}
+    }
+    Ok(())
+}
+
+/// Removes the entries matching `f`, then offers to remove their known_hosts keys.
+/// Returns how many were removed, or `None` without an ssh config.
+fn remove_entries<F>(f: F) -> Result<Option<usize>, ()>
+where
+    F: Fn(&Entry) -> bool,
+{
     let result = open_and_load_config(false, true)?;
     if result.is_none() {
         error!("no entries to remove");
-        return Ok(());
+        return Ok(None);
     }
     let (mut file, mut entries) = result.unwrap();
-    let mut found = false;
+    let mut removed = Vec::new();
     entries.retain(|entry| {
-        if entry.host == host {
-            found = true;
+        if f(entry) {
+            removed.push((entry.hostname.clone(), entry.port()));
             false
         } else {
             true
         }
     });
-    if !found {
-        error!("entry '{}' not found", host);
-        return Err(());
+    if removed.is_empty() {
+        return Ok(Some(0));
     }
     write_entries!(file, entries);
-    Ok(())
+    for (hostname, port) in &removed {
+        known_hosts::offer_to_remove_keys(hostname, *port)?;
+    }
+    Ok(Some(removed.len()))
+}
+
+/// Sorts the entries by alias. Catch-all blocks like `Host *` and `Match` keep their
+/// relative order below the specific hosts, as ssh uses the first value it obtains.
+pub fn sort_entries() -> Result<(), ()> {
+    fn rank(entry: &Entry) -> u8 {
+        match entry.block {
+            Block::Global => 0,
+            _ if entry.is_wildcard() => 2,
+            _ => 1,
+        }
+    }
+    update_entries(|entries| {
+        // The sort is stable, so the catch-all blocks keep their order.
+        entries.sort_by(|a, b| {
+            rank(a).cmp(&rank(b)).then_with(|| match rank(a) {
+                1 => a.host.cmp(&b.host),
+                _ => std::cmp::Ordering::Equal,
+            })
+        });
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "# global comment
+Include=~/.ssh/extra
+
+Host web
+\t# the web server
+\tHostName=web.example.com
+\tUser = alice
+\tIdentityFile=~/.ssh/id_web
+
+# databases
+Host db
+  HostName db.example.com
+  User bob
+  Port 2222
+";
+
+    #[test]
+    fn parse_equals_forms() {
+        let entries = parse_entries(CONFIG).unwrap();
+        let web = entries.iter().find(|x| x.has_alias("web")).unwrap();
+        assert_eq!(web.hostname, "web.example.com");
+        assert_eq!(web.user, "alice");
+        assert_eq!(web.option("IdentityFile"), Some("~/.ssh/id_web"));
+        assert_eq!(entries[0].option("Include"), Some("~/.ssh/extra"));
+        let db = entries.iter().find(|x| x.has_alias("db")).unwrap();
+        assert_eq!(db.comments, ["# databases"]);
+    }
+
+    #[test]
+    fn render_keeps_lines() {
+        let entries = parse_entries(CONFIG).unwrap();
+        assert_eq!(render_entries(&entries), CONFIG);
+    }
+
+    #[test]
+    fn modify_keeps_other_lines() {
+        let mut entries = parse_entries(CONFIG).unwrap();
+        let web = entries.iter_mut().find(|x| x.has_alias("web")).unwrap();
+        web.user = "carol".to_string();
+        web.set_option("ProxyJump", Some("bastion".to_string()));
+        web.set_meta("tags", Some("x".to_string()));
+        let db = entries.iter_mut().find(|x| x.has_alias("db")).unwrap();
+        db.port = Some(2223);
+        let mut new = Entry::new(Block::Host, "cache");
+        new.hostname = "cache.example.com".to_string();
+        new.user = "dave".to_string();
+        entries.insert(2, new);
+
+        let expected = "# global comment
+Include=~/.ssh/extra
+
+Host web
+\t# sshh: tags=x
+\t# the web server
+\tHostName=web.example.com
+\tUser = carol
+\tIdentityFile=~/.ssh/id_web
+\tProxyJump bastion
+
+Host cache
+\tHostName cache.example.com
+\tUser dave
+# databases
+Host db
+  HostName db.example.com
+  User bob
+  Port 2223
+";
+        assert_eq!(render_entries(&entries), expected);
+    }
 }
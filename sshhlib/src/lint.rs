@@ -0,0 +1,372 @@
+use std::path::Path;
+
+use simple_logger::{custom, error, warn};
+
+use crate::{
+    expand_home, match_pattern, print_diff, split_option, write_mode, WriteMode, META_PREFIX,
+};
+
+/// The keywords documented in ssh_config(5), including the deprecated ones ssh still accepts.
+const KEYWORDS: &[&str] = &[
+    "addkeystoagent", "addressfamily", "batchmode", "bindaddress", "bindinterface",
+    "canonicaldomains", "canonicalizefallbacklocal", "canonicalizehostname",
+    "canonicalizemaxdots", "canonicalizepermittedcnames", "casignaturealgorithms",
+    "certificatefile", "challengeresponseauthentication", "channeltimeout", "checkhostip",
+    "ciphers", "clearallforwardings", "compression", "connectionattempts", "connecttimeout",
+    "controlmaster", "controlpath", "controlpersist", "dynamicforward",
+    "enableescapecommandline", "enablesshkeysign", "escapechar", "exitonforwardfailure",
+    "fingerprinthash", "forkafterauthentication", "forwardagent", "forwardx11",
+    "forwardx11timeout", "forwardx11trusted", "gatewayports", "globalknownhostsfile",
+    "gssapiauthentication", "gssapidelegatecredentials", "hashknownhosts", "host",
+    "hostbasedacceptedalgorithms", "hostbasedauthentication", "hostbasedkeytypes",
+    "hostkeyalgorithms", "hostkeyalias", "hostname", "identitiesonly", "identityagent",
+    "identityfile", "ignoreunknown", "include", "ipqos", "kbdinteractiveauthentication",
+    "kbdinteractivedevices", "kexalgorithms", "knownhostscommand", "localcommand",
+    "localforward", "loglevel", "logverbose", "macs", "match",
+    "nohostauthenticationforlocalhost", "numberofpasswordprompts", "obscurekeystroketiming",
+    "passwordauthentication", "permitlocalcommand", "permitremoteopen", "pkcs11provider",
+    "port", "preferredauthentications", "proxycommand", "proxyjump", "proxyusefdpass",
+    "pubkeyacceptedalgorithms", "pubkeyacceptedkeytypes", "pubkeyauthentication",
+    "rekeylimit", "remotecommand", "remoteforward", "requesttty", "requiredrsasize",
+    "revokedhostkeys", "securitykeyprovider", "sendenv", "serveralivecountmax",
+    "serveraliveinterval", "sessiontype", "setenv", "stdinnull", "streamlocalbindmask",
+    "streamlocalbindunlink", "stricthostkeychecking", "syslogfacility", "tag",
+    "tcpkeepalive", "tunnel", "tunneldevice", "updatehostkeys", "user",
+    "userknownhostsfile", "verifyhostkeydns", "visualhostkey", "xauthlocation",
+];
+
+/// Options which may be given several times, each occurrence adding to the previous ones.
+const CUMULATIVE: &[&str] = &[
+    "certificatefile", "dynamicforward", "identityfile", "include", "localforward",
+    "remoteforward", "sendenv", "setenv",
+];
+
+/// Options sshh manages for the entries it lists; Match blocks are invisible to it.
+const ENTRY_KEYWORDS: &[&str] = &["hostname", "user", "port"];
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+struct Finding {
+    line: usize,
+    severity: Severity,
+    message: String,
+    /// Whether `--fix` may delete the line.
+    removable: bool,
+}
+
+struct Opt {
+    line: usize,
+    keyword: String,
+    value: String,
+}
+
+enum Kind {
+    Global,
+    Host(Vec<String>),
+    Match,
+}
+
+struct Block {
+    line: usize,
+    kind: Kind,
+    options: Vec<Opt>,
+    meta_lines: Vec<usize>,
+}
+
+fn parse_blocks(content: &str) -> Vec<Block> {
+    let mut blocks = vec![Block {
+        line: 0,
+        kind: Kind::Global,
+        options: Vec::new(),
+        meta_lines: Vec::new(),
+    }];
+    for (i, line) in content.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.starts_with(META_PREFIX) {
+            blocks.last_mut().unwrap().meta_lines.push(line_no);
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((keyword, value)) = split_option(line) else {
+            continue;
+        };
+        let keyword = keyword.to_lowercase();
+        let kind = match keyword.as_str() {
+            "host" => Kind::Host(value.split_whitespace().map(str::to_lowercase).collect()),
+            "match" => Kind::Match,
+            _ => {
+                blocks.last_mut().unwrap().options.push(Opt {
+                    line: line_no,
+                    keyword,
+                    value: value.to_string(),
+                });
+                continue;
+            }
+        };
+        blocks.push(Block {
+            line: line_no,
+            kind,
+            options: Vec::new(),
+            meta_lines: Vec::new(),
+        });
+    }
+    blocks
+}
+
+fn is_wildcard(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Whether every host matched by `patterns` is also matched by `wildcard`.
+fn covers(wildcard: &[String], patterns: &[String]) -> bool {
+    if wildcard.iter().any(|x| x.starts_with('!')) {
+        return false; // negations make this hard to tell, be conservative
+    }
+    let positive: Vec<&String> = patterns.iter().filter(|x| !x.starts_with('!')).collect();
+    !positive.is_empty()
+        && positive
+            .iter()
+            .all(|p| wildcard.iter().any(|w| is_wildcard(w) && match_pattern(w, p)))
+}
+
+fn check(blocks: &[Block]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let ignored: Vec<String> = blocks
+        .iter()
+        .flat_map(|x| &x.options)
+        .filter(|x| x.keyword == "ignoreunknown")
+        .flat_map(|x| x.value.split(',').map(str::to_lowercase))
+        .collect();
+
+    let mut aliases: Vec<(&str, usize)> = Vec::new();
+    for (index, block) in blocks.iter().enumerate() {
+        if let Kind::Host(patterns) = &block.kind {
+            for pattern in patterns.iter().filter(|x| !is_wildcard(x)) {
+                // Patterns are lowercased already, ssh compares them case-insensitively.
+                if let Some((_, line)) = aliases.iter().find(|(x, _)| x == pattern) {
+                    findings.push(Finding {
+                        line: block.line,
+                        severity: Severity::Error,
+                        message: format!(
+                            "alias '{}' is already defined at line {} (aliases are case-insensitive)",
+                            pattern, line
+                        ),
+                        removable: false,
+                    });
+                } else {
+                    aliases.push((pattern, block.line));
+                }
+            }
+        }
+
+        let mut seen: Vec<&str> = Vec::new();
+        for opt in &block.options {
+            let keyword = opt.keyword.as_str();
+            if !KEYWORDS.contains(&keyword) {
+                if !ignored.iter().any(|x| match_pattern(x, keyword)) {
+                    findings.push(Finding {
+                        line: opt.line,
+                        severity: Severity::Error,
+                        message: format!("unknown keyword '{}'", keyword),
+                        removable: false,
+                    });
+                }
+                continue;
+            }
+            if seen.contains(&keyword) && !CUMULATIVE.contains(&keyword) {
+                findings.push(Finding {
+                    line: opt.line,
+                    severity: Severity::Warning,
+                    message: format!("'{}' is set again in the same block and ignored", keyword),
+                    removable: true,
+                });
+            }
+            seen.push(keyword);
+
+            match keyword {
+                "port" if !matches!(opt.value.parse::<u16>(), Ok(x) if x != 0) => {
+                    findings.push(Finding {
+                        line: opt.line,
+                        severity: Severity::Error,
+                        message: format!("invalid port '{}'", opt.value),
+                        removable: false,
+                    });
+                }
+                "identityfile" | "certificatefile" => {
+                    let value = opt.value.trim_matches('"');
+                    if value.contains('%') || value.contains("${") || value == "none" {
+                        continue; // expanded by ssh at connection time
+                    }
+                    if let Ok(path) = expand_home(value) {
+                        if !path.exists() {
+                            findings.push(Finding {
+                                line: opt.line,
+                                severity: Severity::Warning,
+                                message: format!("'{}' does not exist", path.display()),
+                                removable: false,
+                            });
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            if let Kind::Match = block.kind {
+                if ENTRY_KEYWORDS.contains(&keyword) {
+                    findings.push(Finding {
+                        line: opt.line,
+                        severity: Severity::Info,
+                        message: format!(
+                            "'{}' inside Match is ignored by sshh's listings",
+                            keyword
+                        ),
+                        removable: false,
+                    });
+                }
+            }
+        }
+
+        if let Kind::Match = block.kind {
+            for line in &block.meta_lines {
+                findings.push(Finding {
+                    line: *line,
+                    severity: Severity::Warning,
+                    message: "sshh metadata inside Match is ignored".to_string(),
+                    removable: false,
+                });
+            }
+        }
+
+        // ssh takes the first value it obtains, so an earlier wildcard block wins.
+        let Kind::Host(patterns) = &block.kind else {
+            continue;
+        };
+        for earlier in &blocks[..index] {
+            let Kind::Host(wildcard) = &earlier.kind else {
+                continue;
+            };
+            if !covers(wildcard, patterns) {
+                continue;
+            }
+            for opt in &block.options {
+                if CUMULATIVE.contains(&opt.keyword.as_str()) {
+                    continue;
+                }
+                if let Some(x) = earlier.options.iter().find(|x| x.keyword == opt.keyword) {
+                    findings.push(Finding {
+                        line: opt.line,
+                        severity: Severity::Warning,
+                        message: format!(
+                            "'{}' has no effect, it is already set by 'Host {}' at line {}",
+                            opt.keyword,
+                            wildcard.join(" "),
+                            x.line
+                        ),
+                        removable: false,
+                    });
+                }
+            }
+        }
+    }
+    findings.sort_by_key(|x| x.line);
+    findings
+}
+
+/// Lints an ssh config file, `~/.ssh/config` by default; returns whether no errors were found.
+///
+/// With `fix`, the lines which have no effect at all are removed, as the write mode
+/// allows.
+pub fn lint(path: Option<&Path>, fix: bool) -> Result<bool, ()> {
+    let mode = write_mode();
+    let path = match path {
+        Some(x) => x.to_path_buf(),
+        None => crate::home_dir()?.join(".ssh").join("config"),
+    };
+    let content = std::fs::read_to_string(&path).map_err(|e| {
+        error!("failed to read '{}': {}", path.display(), e);
+    })?;
+    let findings = check(&parse_blocks(&content));
+    let display = path.display();
+
+    let mut removed = Vec::new();
+    for finding in &findings {
+        let fixed = if fix && finding.removable {
+            removed.push(finding.line);
+            if mode == WriteMode::DryRun {
+                " (would be fixed)"
+            } else {
+                " (fixed)"
+            }
+        } else {
+            ""
+        };
+        match finding.severity {
+            Severity::Error => error!("{}:{}: {}{}", display, finding.line, finding.message, fixed),
+            Severity::Warning => warn!("{}:{}: {}{}", display, finding.line, finding.message, fixed),
+            Severity::Info => {
+                custom!(title="info"; "{}:{}: {}{}", display, finding.line, finding.message, fixed)
+            }
+        }
+    }
+
+    if !removed.is_empty() {
+        let mut result = String::with_capacity(content.len());
+        for (i, line) in content.lines().enumerate() {
+            if !removed.contains(&(i + 1)) {
+                result.push_str(line);
+                result.push('\n');
+            }
+        }
+        if mode != WriteMode::Write {
+            print_diff(&display.to_string(), &content, &result);
+        }
+        if mode != WriteMode::DryRun {
+            std::fs::write(&path, result).map_err(|e| {
+                error!("failed to write '{}': {}", display, e);
+            })?;
+        }
+    }
+
+    let errors = findings
+        .iter()
+        .filter(|x| x.severity == Severity::Error)
+        .count();
+    if findings.is_empty() {
+        custom!(title="lint"; "{}: no problems found", display);
+    }
+    Ok(errors == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_options() {
+        let content = [
+            "Include ~/.ssh/a",
+            "Include ~/.ssh/b",
+            "Host web",
+            "    IdentityFile none",
+            "    IdentityFile none",
+            "    Port 22",
+            "    Port 2222",
+        ]
+        .join("\n");
+        let findings = check(&parse_blocks(&content));
+        let repeated = findings
+            .iter()
+            .filter(|x| x.message.contains("set again"))
+            .map(|x| (x.line, x.removable))
+            .collect::<Vec<_>>();
+        assert_eq!(repeated, [(7, true)]);
+    }
+}
@@ -0,0 +1,181 @@
+use std::path::PathBuf;
+
+use simple_logger::{custom, error, warn};
+
+use crate::transport::{self, Tool};
+use crate::{home_dir, load_entries, override_system_entry, perms, update_entries, write_mode, Entry, WriteMode};
+
+/// Master sockets live in `~/.ssh/sshh-mux`, named `<user>@<host>:<port>`.
+const MUX_DIR: &str = "sshh-mux";
+const CONTROL_PATH: &str = "~/.ssh/sshh-mux/%r@%h:%p";
+/// How long a master stays up after its last session closed.
+const CONTROL_PERSIST: &str = "10m";
+
+fn mux_dir() -> Result<PathBuf, ()> {
+    Ok(home_dir()?.join(".ssh").join(MUX_DIR))
+}
+
+/// Enables or disables connection sharing for `alias`, or every entry without one.
+pub fn set_mux(alias: Option<&str>, enable: bool) -> Result<(), ()> {
+    if enable && cfg!(windows) {
+        error!("connection sharing is not supported by ssh on Windows");
+        return Err(());
+    }
+    if enable && write_mode() != WriteMode::DryRun {
+        let dir = mux_dir()?;
+        perms::create_dir(&dir).map_err(|e| {
+            error!("failed to create '{}': {}", dir.display(), e);
+        })?;
+    }
+    let mut count = 0;
+    update_entries(|entries| {
+        if let Some(alias) = alias {
+            override_system_entry(entries, alias);
+        }
+        for entry in entries.iter_mut() {
+            let selected = match alias {
+                Some(alias) => entry.has_alias(alias),
+                None => entry.is_listed(),
+            };
+            if !selected {
+                continue;
+            }
+            let value = |x: &str| if enable { Some(x.to_string()) } else { None };
+            entry.set_option("ControlMaster", value("auto"));
+            entry.set_option("ControlPath", value(CONTROL_PATH));
+            entry.set_option("ControlPersist", value(CONTROL_PERSIST));
+            count += 1;
+        }
+        if count == 0 {
+            match alias {
+                Some(alias) => error!("entry '{}' not found", alias),
+                None => error!("no entries"),
+            }
+            return Err(());
+        }
+        Ok(())
+    })?;
+    let state = if enable { "on" } else { "off" };
+    match alias {
+        Some(alias) => custom!(title=alias; "connection sharing {}", state),
+        None => custom!(title="mux"; "connection sharing {} for {} entries", state, count),
+    }
+    Ok(())
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Socket {
+    user: String,
+    host: String,
+    port: u16,
+    /// Whether a master still accepts connections on it.
+    live: bool,
+}
+
+impl Socket {
+    fn is_used_by(&self, entry: &Entry) -> bool {
+        entry.is_listed()
+            && entry.user == self.user
+            && entry.hostname == self.host
+            && entry.port() == self.port
+    }
+}
+
+fn load_sockets() -> Result<Vec<Socket>, ()> {
+    let dir = mux_dir()?;
+    let read_dir = match std::fs::read_dir(&dir) {
+        Ok(x) => x,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut result = Vec::new();
+    for file in read_dir.flatten() {
+        let name = file.file_name().to_string_lossy().into_owned();
+        let Some((user, endpoint)) = name.split_once('@') else {
+            continue;
+        };
+        let Some((host, port)) = endpoint.rsplit_once(':') else {
+            continue;
+        };
+        let Ok(port) = port.parse() else {
+            continue;
+        };
+        result.push(Socket {
+            user: user.to_string(),
+            host: host.to_string(),
+            port,
+            live: is_live(&file.path()),
+        });
+    }
+    result.sort();
+    Ok(result)
+}
+
+#[cfg(unix)]
+fn is_live(path: &std::path::Path) -> bool {
+    std::os::unix::net::UnixStream::connect(path).is_ok()
+}
+
+#[cfg(windows)]
+fn is_live(_path: &std::path::Path) -> bool {
+    false
+}
+
+/// Lists the master sockets with the aliases connecting through them.
+pub fn list_masters() -> Result<(), ()> {
+    let sockets = load_sockets()?;
+    if sockets.is_empty() {
+        warn!("no master connections");
+        return Ok(());
+    }
+    let entries = load_entries()?;
+    for socket in sockets {
+        let aliases: Vec<&str> = entries
+            .iter()
+            .filter(|x| socket.is_used_by(x))
+            .map(|x| x.host.as_str())
+            .collect();
+        let aliases = if aliases.is_empty() {
+            "no alias".to_string()
+        } else {
+            aliases.join(",")
+        };
+        let state = if socket.live { "live" } else { "stale" };
+        custom!(title=aliases; "{}@{}:{}, {}", socket.user, socket.host, socket.port, state);
+    }
+    Ok(())
+}
+
+/// Asks the master connection of `alias`, or every live one, to exit.
+pub fn stop_master(alias: Option<&str>) -> Result<(), ()> {
+    let targets = match alias {
+        Some(alias) => vec![alias.to_string()],
+        None => {
+            let entries = load_entries()?;
+            let mut targets = Vec::new();
+            for socket in load_sockets()?.iter().filter(|x| x.live) {
+                if let Some(entry) = entries.iter().find(|x| socket.is_used_by(x)) {
+                    targets.push(entry.host.clone());
+                }
+            }
+            if targets.is_empty() {
+                warn!("no live master connections");
+            }
+            targets
+        }
+    };
+
+    let mut result = Ok(());
+    for alias in targets {
+        let args = ["-O".to_string(), "exit".to_string(), alias.clone()];
+        let status = transport::command(Tool::Ssh, &args)?
+            .status()
+            .map_err(|e| {
+                error!("failed to spawn child process: {}", e);
+            })?;
+        if !status.success() {
+            error!("failed to stop the master connection of '{}': {}", alias, status);
+            result = Err(());
+        }
+    }
+    result
+}
@@ -0,0 +1,123 @@
+use std::fs::OpenOptions;
+use std::path::Path;
+
+#[cfg(unix)]
+use simple_logger::{custom, error};
+use simple_logger::warn;
+
+#[cfg(unix)]
+use crate::home_dir;
+
+/// Creates the ssh directory, private to the user as ssh expects.
+#[cfg(unix)]
+pub(crate) fn create_dir(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(path)
+}
+
+#[cfg(windows)]
+pub(crate) fn create_dir(path: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(path)
+}
+
+/// Makes files created through `options` readable by the user only.
+#[cfg(unix)]
+pub(crate) fn private_file(options: &mut OpenOptions) -> &mut OpenOptions {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(0o600)
+}
+
+#[cfg(windows)]
+pub(crate) fn private_file(options: &mut OpenOptions) -> &mut OpenOptions {
+    options
+}
+
+/// Warns about what would make ssh refuse `path`: an owner other than the user
+/// or root, or write permission for the group or others.
+#[cfg(unix)]
+fn check_path(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return true;
+    };
+    let uid = unsafe { libc::getuid() };
+    if metadata.uid() != uid && metadata.uid() != 0 {
+        warn!("'{}' is owned by another user", path.display());
+        return false;
+    }
+    if metadata.mode() & 0o022 != 0 {
+        warn!(
+            "'{}' is writable by group or others (mode {:o})",
+            path.display(),
+            metadata.mode() & 0o777
+        );
+        return false;
+    }
+    true
+}
+
+/// Checks the ownership and permissions of `~/.ssh` and its config.
+#[cfg(unix)]
+pub(crate) fn check_permissions() -> Result<(), ()> {
+    let dir = home_dir()?.join(".ssh");
+    let ok = check_path(&dir) & check_path(&dir.join("config"));
+    if !ok {
+        warn!("ssh may refuse to use its config, run 'sshh fix-perms'");
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+pub(crate) fn check_permissions() -> Result<(), ()> {
+    Ok(())
+}
+
+/// Restricts `~/.ssh` to mode 0700, and its config and private keys to mode 0600.
+#[cfg(unix)]
+pub fn fix_permissions() -> Result<(), ()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let dir = home_dir()?.join(".ssh");
+    let mut paths = vec![(dir.clone(), 0o700)];
+    let read_dir = std::fs::read_dir(&dir).map_err(|e| {
+        error!("failed to read '{}': {}", dir.display(), e);
+    })?;
+    for file in read_dir.flatten() {
+        let name = file.file_name().to_string_lossy().into_owned();
+        let private = name == "config" || (name.starts_with("id_") && !name.ends_with(".pub"));
+        if private && file.file_type().is_ok_and(|x| x.is_file()) {
+            paths.push((file.path(), 0o600));
+        }
+    }
+
+    let uid = unsafe { libc::getuid() };
+    let mut result = Ok(());
+    for (path, mode) in paths {
+        let metadata = std::fs::metadata(&path).map_err(|e| {
+            error!("failed to stat '{}': {}", path.display(), e);
+        })?;
+        if metadata.uid() != uid && metadata.uid() != 0 {
+            error!("'{}' is owned by another user, change its owner first", path.display());
+            result = Err(());
+            continue;
+        }
+        let current = metadata.mode() & 0o777;
+        if current == mode {
+            continue;
+        }
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).map_err(|e| {
+            error!("failed to change the mode of '{}': {}", path.display(), e);
+        })?;
+        custom!(title="fixed"; "'{}' {:o} -> {:o}", path.display(), current, mode);
+    }
+    result
+}
+
+#[cfg(windows)]
+pub fn fix_permissions() -> Result<(), ()> {
+    warn!("'fix-perms' has nothing to do on Windows");
+    Ok(())
+}
@@ -0,0 +1,462 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use simple_logger::{custom, error, warn};
+
+use crate::transport::ssh_destination;
+use crate::{data_dir, expiry, load_entries, perms};
+
+/// Pauses longer than this are shortened when replaying.
+const MAX_IDLE: Duration = Duration::from_secs(3);
+
+/// Returns the directory of the transcripts of `name`, an alias or an ad-hoc host.
+fn log_dir(name: &str) -> Result<PathBuf, ()> {
+    let name: String = name
+        .chars()
+        .map(|x| match x {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => x,
+            _ => '_',
+        })
+        .collect();
+    Ok(data_dir()?.join("logs").join(name))
+}
+
+/// Returns the name to record the session of the ssh arguments under, if it should be:
+/// always with `force`, otherwise only if the destination's entry has `log=true`.
+pub fn transcript_name(args: &[String], force: bool) -> Result<Option<String>, ()> {
+    let Some(index) = ssh_destination(args) else {
+        if force {
+            error!("'--log' requires a destination");
+            return Err(());
+        }
+        return Ok(None);
+    };
+    let destination = &args[index];
+    let destination = destination.strip_prefix("ssh://").unwrap_or(destination);
+    let host = match destination.split_once('@') {
+        Some((_, host)) => host,
+        None => destination,
+    };
+    if force {
+        return Ok(Some(host.to_string()));
+    }
+    let enabled = load_entries()?
+        .iter()
+        .find(|x| x.has_alias(host))
+        .and_then(|x| x.meta("log"))
+        .is_some_and(|x| x == "true" || x == "yes");
+    Ok(enabled.then(|| host.to_string()))
+}
+
+/// Writes the events of an asciicast v2 file, see https://docs.asciinema.org/manual/asciicast/v2/
+#[cfg_attr(windows, allow(dead_code))]
+struct Transcript {
+    writer: Option<LineWriter<File>>,
+    start: Instant,
+    /// The tail of the output which is not a complete UTF-8 sequence yet.
+    pending: Vec<u8>,
+}
+
+#[cfg_attr(windows, allow(dead_code))]
+impl Transcript {
+    fn create(name: &str, width: u16, height: u16) -> Result<Self, ()> {
+        let dir = log_dir(name)?;
+        perms::create_dir(&dir).map_err(|e| {
+            error!("failed to create '{}': {}", dir.display(), e);
+        })?;
+        let now = expiry::now();
+        // ':' is not allowed in file names on Windows.
+        let stem = expiry::format_timestamp(now).replace(':', "-");
+        // Sessions started in the same second get a suffix, which sorts after the first one.
+        let mut suffix = 1;
+        let (path, file) = loop {
+            let path = match suffix {
+                1 => dir.join(format!("{}.log", stem)),
+                n => dir.join(format!("{}_{}.log", stem, n)),
+            };
+            match perms::private_file(&mut File::options())
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => break (path, file),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => suffix += 1,
+                Err(e) => {
+                    error!("failed to create '{}': {}", path.display(), e);
+                    return Err(());
+                }
+            }
+        };
+        let mut env = serde_json::Map::new();
+        for var in ["TERM", "SHELL"] {
+            if let Ok(x) = std::env::var(var) {
+                env.insert(var.to_string(), Value::String(x));
+            }
+        }
+        let header = json!({
+            "version": 2,
+            "width": width,
+            "height": height,
+            "timestamp": now,
+            "title": name,
+            "env": env,
+        });
+        let mut writer = LineWriter::new(file);
+        writeln!(writer, "{}", header).map_err(|e| {
+            error!("failed to write '{}': {}", path.display(), e);
+        })?;
+        custom!(title="log"; "recording to '{}'", path.display());
+        Ok(Transcript {
+            writer: Some(writer),
+            start: Instant::now(),
+            pending: Vec::new(),
+        })
+    }
+
+    fn event(&mut self, code: &str, data: &str) {
+        let Some(writer) = &mut self.writer else {
+            return;
+        };
+        let time = (self.start.elapsed().as_secs_f64() * 1e6).round() / 1e6;
+        if let Err(e) = writeln!(writer, "{}", json!([time, code, data])) {
+            warn!("failed to write the transcript, recording stopped: {}", e);
+            self.writer = None;
+        }
+    }
+
+    fn output(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            // An incomplete sequence at the end, wait for the rest of it.
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        let data = String::from_utf8_lossy(&self.pending[..valid]).into_owned();
+        self.pending.drain(..valid);
+        if !data.is_empty() {
+            self.event("o", &data);
+        }
+    }
+
+    fn resize(&mut self, width: u16, height: u16) {
+        self.event("r", &format!("{}x{}", width, height));
+    }
+}
+
+#[cfg(unix)]
+mod pty {
+    use std::fs::File;
+    use std::io::Error;
+    use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use simple_logger::error;
+
+    pub(super) fn open() -> Result<(File, File), ()> {
+        unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                error!("failed to open a pseudo-terminal: {}", Error::last_os_error());
+                return Err(());
+            }
+            let master = File::from_raw_fd(fd);
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                error!("failed to unlock the pseudo-terminal: {}", Error::last_os_error());
+                return Err(());
+            }
+            let name = libc::ptsname(fd);
+            if name.is_null() {
+                error!("failed to get the pseudo-terminal name: {}", Error::last_os_error());
+                return Err(());
+            }
+            let fd = libc::open(name, libc::O_RDWR | libc::O_NOCTTY);
+            if fd < 0 {
+                error!("failed to open the pseudo-terminal: {}", Error::last_os_error());
+                return Err(());
+            }
+            Ok((master, File::from_raw_fd(fd)))
+        }
+    }
+
+    pub(super) fn window_size(fd: RawFd) -> Option<libc::winsize> {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        let result = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) };
+        (result == 0 && size.ws_col > 0).then_some(size)
+    }
+
+    pub(super) fn set_window_size(file: &File, size: &libc::winsize) {
+        unsafe { libc::ioctl(file.as_raw_fd(), libc::TIOCSWINSZ, size) };
+    }
+
+    /// Makes the child the leader of a new session, controlled by its terminal.
+    pub(super) fn make_controlling_terminal() -> std::io::Result<()> {
+        unsafe {
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Puts stdin into raw mode until dropped, so keys reach the child unprocessed.
+    pub(super) struct RawMode(libc::termios);
+
+    impl RawMode {
+        pub(super) fn enable() -> Option<RawMode> {
+            unsafe {
+                let mut original: libc::termios = std::mem::zeroed();
+                if libc::tcgetattr(0, &mut original) != 0 {
+                    return None;
+                }
+                let mut raw = original;
+                libc::cfmakeraw(&mut raw);
+                libc::tcsetattr(0, libc::TCSANOW, &raw);
+                Some(RawMode(original))
+            }
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            unsafe { libc::tcsetattr(0, libc::TCSANOW, &self.0) };
+        }
+    }
+
+    static RESIZED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_resize(_: libc::c_int) {
+        RESIZED.store(true, Ordering::Relaxed);
+    }
+
+    pub(super) fn watch_resize() {
+        let handler: extern "C" fn(libc::c_int) = on_resize;
+        unsafe { libc::signal(libc::SIGWINCH, handler as libc::sighandler_t) };
+    }
+
+    pub(super) fn take_resized() -> bool {
+        RESIZED.swap(false, Ordering::Relaxed)
+    }
+
+    /// Waits until `master` or, if still open, stdin is readable; returns their readiness.
+    pub(super) fn poll(master: &File, stdin_open: bool) -> Result<(bool, bool), Error> {
+        let events = libc::POLLIN | libc::POLLHUP | libc::POLLERR;
+        let mut fds = [
+            libc::pollfd {
+                fd: master.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: if stdin_open { 0 } else { -1 },
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok((fds[0].revents & events != 0, fds[1].revents & events != 0))
+    }
+
+    /// Reads stdin without the buffering of [`std::io::Stdin`], which would hide data from `poll`.
+    pub(super) fn read_stdin(buf: &mut [u8]) -> Result<usize, Error> {
+        let n = unsafe { libc::read(0, buf.as_mut_ptr().cast(), buf.len()) };
+        if n < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+}
+
+/// Runs `cmd` under a pseudo-terminal, recording its output and the terminal size
+/// changes to a new transcript of `name`.
+#[cfg(unix)]
+pub fn record(mut cmd: Command, name: &str) -> Result<ExitStatus, ()> {
+    use std::io::Read;
+    use std::os::unix::process::CommandExt;
+    use std::process::Stdio;
+
+    let (mut master, slave) = pty::open()?;
+    let mut size = pty::window_size(0).unwrap_or(libc::winsize {
+        ws_row: 24,
+        ws_col: 80,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    });
+    pty::set_window_size(&master, &size);
+    let mut transcript = Transcript::create(name, size.ws_col, size.ws_row)?;
+
+    let stdio = |file: &File| {
+        file.try_clone().map(Stdio::from).map_err(|e| {
+            error!("failed to duplicate the pseudo-terminal: {}", e);
+        })
+    };
+    cmd.stdin(stdio(&slave)?)
+        .stdout(stdio(&slave)?)
+        .stderr(Stdio::from(slave));
+    unsafe { cmd.pre_exec(pty::make_controlling_terminal) };
+    // SIGTERM, SIGHUP and SIGINT go to the child, which then exits and ends the loop
+    // below, so the terminal leaves raw mode instead of sshh dying in it.
+    let forwarding = childlib::Forwarding::install();
+    let mut child = cmd.spawn().map_err(|e| {
+        error!("failed to spawn child process: {}", e);
+    })?;
+    forwarding.set_child(child.id());
+    // Close our ends of the terminal, so reading the master fails once the child exits.
+    drop(cmd);
+
+    // The child gets SIGWINCH from its own terminal once the size is passed on.
+    pty::watch_resize();
+    let raw_mode = pty::RawMode::enable();
+    let mut stdout = std::io::stdout();
+    let mut stdin_open = true;
+    let mut buf = [0u8; 8192];
+    loop {
+        if pty::take_resized() {
+            match pty::window_size(0) {
+                Some(x) if (x.ws_col, x.ws_row) != (size.ws_col, size.ws_row) => {
+                    size = x;
+                    pty::set_window_size(&master, &size);
+                    transcript.resize(size.ws_col, size.ws_row);
+                }
+                _ => {}
+            }
+        }
+        let (output, input) = match pty::poll(&master, stdin_open) {
+            Ok(x) => x,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                warn!("failed to wait for the pseudo-terminal: {}", e);
+                break;
+            }
+        };
+        if output {
+            match master.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let _ = stdout.write_all(&buf[..n]);
+                    let _ = stdout.flush();
+                    transcript.output(&buf[..n]);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                // EIO once every process holding the terminal has exited
+                Err(_) => break,
+            }
+        }
+        if input {
+            match pty::read_stdin(&mut buf) {
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Ok(0) | Err(_) => stdin_open = false,
+                Ok(n) => {
+                    if master.write_all(&buf[..n]).is_err() {
+                        stdin_open = false;
+                    }
+                }
+            }
+        }
+    }
+    drop(raw_mode);
+
+    let status = child.wait().map_err(|e| {
+        error!("failed to wait for child process: {}", e);
+    });
+    drop(forwarding);
+    status
+}
+
+#[cfg(windows)]
+pub fn record(_cmd: Command, _name: &str) -> Result<ExitStatus, ()> {
+    error!("session logging is not supported on Windows");
+    Err(())
+}
+
+/// Plays a transcript back: `target` is a transcript file, or an alias whose latest one is played.
+pub fn replay(target: &str) -> Result<(), ()> {
+    let path = if Path::new(target).is_file() {
+        PathBuf::from(target)
+    } else {
+        let dir = log_dir(target)?;
+        let latest = std::fs::read_dir(&dir)
+            .ok()
+            .and_then(|x| x.flatten().map(|x| x.path()).max());
+        latest.ok_or_else(|| {
+            error!("no transcript file or transcripts of '{}' found", target);
+        })?
+    };
+    let file = File::open(&path).map_err(|e| {
+        error!("failed to open '{}': {}", path.display(), e);
+    })?;
+    let mut lines = BufReader::new(file).lines().map_while(Result::ok);
+    let header: Option<Value> = lines.next().and_then(|x| serde_json::from_str(&x).ok());
+    if header.as_ref().and_then(|x| x["version"].as_u64()) != Some(2) {
+        error!("'{}' is not an asciicast v2 file", path.display());
+        return Err(());
+    }
+
+    let mut stdout = std::io::stdout();
+    let mut last = 0.0;
+    for line in lines {
+        let Ok(Value::Array(event)) = serde_json::from_str(&line) else {
+            continue;
+        };
+        let (Some(time), Some("o"), Some(data)) = (
+            event.first().and_then(Value::as_f64),
+            event.get(1).and_then(Value::as_str),
+            event.get(2).and_then(Value::as_str),
+        ) else {
+            continue; // input or resize events can't be replayed on this terminal
+        };
+        let delay = Duration::from_secs_f64((time - last).max(0.0));
+        std::thread::sleep(delay.min(MAX_IDLE));
+        last = time;
+        let _ = stdout.write_all(data.as_bytes());
+        let _ = stdout.flush();
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_stub_sessions() {
+        let dir = std::env::temp_dir().join(format!("sshh-record-test-{}", std::process::id()));
+        std::env::set_var("XDG_DATA_HOME", &dir);
+
+        // Two sessions in the same second must not collide.
+        for code in [3, 4] {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", &format!("printf 'hello\\n'; exit {}", code)]);
+            let status = record(cmd, "stub").unwrap();
+            assert_eq!(status.code(), Some(code));
+        }
+
+        let mut files: Vec<PathBuf> = std::fs::read_dir(log_dir("stub").unwrap())
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        for file in &files {
+            let content = std::fs::read_to_string(file).unwrap();
+            let mut lines = content.lines();
+            let header: Value = serde_json::from_str(lines.next().unwrap()).unwrap();
+            assert_eq!(header["version"], 2);
+            assert_eq!(header["title"], "stub");
+            let output: String = lines
+                .map(|x| serde_json::from_str::<Value>(x).unwrap())
+                .filter(|x| x[1] == "o")
+                .map(|x| x[2].as_str().unwrap().to_string())
+                .collect();
+            // The terminal turns '\n' into "\r\n".
+            assert_eq!(output, "hello\r\n");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,112 @@
+use std::io::ErrorKind;
+
+use simple_logger::error;
+
+use crate::{home_dir, match_pattern};
+
+/// The settings of sshh itself, loaded from `~/.ssh/sshh_config`.
+///
+/// The file uses the ssh_config syntax, one `Keyword value` per line:
+///
+/// ```text
+/// Ssh C:\Program Files\OpenSSH\ssh.exe
+/// Scp C:\Program Files\OpenSSH\scp.exe
+///
+/// Template corp *.corp.example.com
+///     User svc
+///     IdentityFile ~/.ssh/corp
+///     ProxyJump bastion
+/// ```
+///
+/// As with `Host` in ssh_config, the lines after a `Template` belong to it.
+#[derive(Default)]
+pub(crate) struct Settings {
+    options: Vec<(String, String)>,
+    templates: Vec<Template>,
+}
+
+/// The ssh options applied to the entries added for a hostname matching one of
+/// `patterns`, or explicitly by name.
+pub(crate) struct Template {
+    pub(crate) name: String,
+    patterns: Vec<String>,
+    pub(crate) options: Vec<(String, String)>,
+}
+
+impl Template {
+    /// Matches `hostname` like ssh matches a `Host` line, including `!` negations.
+    fn matches(&self, hostname: &str) -> bool {
+        let mut matched = false;
+        for pattern in &self.patterns {
+            match pattern.strip_prefix('!') {
+                Some(x) if match_pattern(x, hostname) => return false,
+                Some(_) => {}
+                None => matched |= match_pattern(pattern, hostname),
+            }
+        }
+        matched
+    }
+}
+
+impl Settings {
+    /// Returns the value of `keyword`, compared case-insensitively.
+    pub(crate) fn get(&self, keyword: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(keyword))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub(crate) fn template(&self, name: &str) -> Option<&Template> {
+        self.templates.iter().find(|x| x.name == name)
+    }
+
+    /// Returns the first template whose patterns match `hostname`.
+    pub(crate) fn find_template(&self, hostname: &str) -> Option<&Template> {
+        self.templates.iter().find(|x| x.matches(hostname))
+    }
+}
+
+fn parse_settings(content: &str) -> Settings {
+    let mut settings = Settings::default();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((keyword, value)) = line.split_once(char::is_whitespace) else {
+            continue; // invalid line
+        };
+        let value = value.trim();
+        if keyword.eq_ignore_ascii_case("template") {
+            let mut words = value.split_whitespace().map(str::to_string);
+            settings.templates.push(Template {
+                name: words.next().unwrap_or_default(),
+                patterns: words.collect(),
+                options: Vec::new(),
+            });
+            continue;
+        }
+        match settings.templates.last_mut() {
+            // Kept as written, since they are copied into the ssh config.
+            Some(template) => template.options.push((keyword.to_string(), value.to_string())),
+            None => {
+                let value = value.trim_matches('"');
+                settings.options.push((keyword.to_string(), value.to_string()));
+            }
+        }
+    }
+    settings
+}
+
+pub(crate) fn load_settings() -> Result<Settings, ()> {
+    let path = home_dir()?.join(".ssh").join("sshh_config");
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Ok(parse_settings(&content)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Settings::default()),
+        Err(e) => {
+            error!("failed to read sshh config file: {}", e);
+            Err(())
+        }
+    }
+}
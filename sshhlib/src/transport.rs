@@ -0,0 +1,275 @@
+use std::process::Command;
+
+use simple_logger::error;
+
+use crate::settings::{load_settings, Settings};
+
+/// The programs which can be driven by the alias database.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Ssh,
+    Scp,
+    Sftp,
+    Mosh,
+    Autossh,
+    Rsync,
+    SshKeygen,
+}
+
+impl Tool {
+    pub fn from_name(name: &str) -> Option<Tool> {
+        let tool = match name {
+            "ssh" => Tool::Ssh,
+            "scp" => Tool::Scp,
+            "sftp" => Tool::Sftp,
+            "mosh" => Tool::Mosh,
+            "autossh" => Tool::Autossh,
+            "rsync" => Tool::Rsync,
+            "ssh-keygen" => Tool::SshKeygen,
+            _ => return None,
+        };
+        Some(tool)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Tool::Ssh => "ssh",
+            Tool::Scp => "scp",
+            Tool::Sftp => "sftp",
+            Tool::Mosh => "mosh",
+            Tool::Autossh => "autossh",
+            Tool::Rsync => "rsync",
+            Tool::SshKeygen => "ssh-keygen",
+        }
+    }
+
+    fn default_program(self) -> String {
+        if cfg!(windows) {
+            format!("{}.exe", self.name())
+        } else {
+            self.name().to_string()
+        }
+    }
+}
+
+/// Returns the program set by the `SSHH_<TOOL>` environment variable or the
+/// `<Tool>` option of the sshh config file, in that order.
+fn program_override(tool: Tool, settings: &Settings) -> Option<String> {
+    let var = format!("SSHH_{}", tool.name().to_uppercase().replace('-', "_"));
+    match std::env::var(var) {
+        Ok(x) if !x.is_empty() => Some(x),
+        _ => settings.get(tool.name()).map(str::to_string),
+    }
+}
+
+/// Builds the command which runs `tool` with `args`, wiring it to the configured ssh program.
+pub fn command(tool: Tool, args: &[String]) -> Result<Command, ()> {
+    let settings = load_settings()?;
+    let args = &match tool {
+        Tool::Ssh | Tool::Autossh => skip_startup_command(args)?,
+        _ => args.to_vec(),
+    };
+    let ssh_override = program_override(Tool::Ssh, &settings);
+    let ssh = ssh_override
+        .clone()
+        .unwrap_or_else(|| Tool::Ssh.default_program());
+    let program = program_override(tool, &settings).unwrap_or_else(|| tool.default_program());
+
+    let mut cmd = Command::new(program);
+    match tool {
+        Tool::Ssh | Tool::SshKeygen => {}
+        Tool::Scp | Tool::Sftp => {
+            if let Some(ssh) = ssh_override {
+                cmd.arg("-S").arg(ssh);
+            }
+            if has_startup_commands()? {
+                cmd.args(["-o", "RemoteCommand=none"]);
+            }
+        }
+        Tool::Autossh => {
+            cmd.env("AUTOSSH_PATH", &ssh);
+            if !args.iter().any(|x| x.starts_with("-M")) {
+                cmd.args(["-M", "0"]);
+            }
+        }
+        Tool::Rsync => {
+            let mut ssh = quote(&ssh);
+            if has_startup_commands()? {
+                ssh.push_str(" -o RemoteCommand=none");
+            }
+            cmd.arg("-e").arg(ssh);
+        }
+        Tool::Mosh => {
+            // mosh resolves the host itself, so aliases must be translated beforehand.
+            let args = translate_mosh_args(args, &ssh)?;
+            cmd.args(args);
+            return Ok(cmd);
+        }
+    }
+    cmd.args(args);
+    Ok(cmd)
+}
+
+fn quote(s: &str) -> String {
+    if s.contains(char::is_whitespace) {
+        format!("\"{}\"", s)
+    } else {
+        s.to_string()
+    }
+}
+
+fn translate_mosh_args(args: &[String], ssh: &str) -> Result<Vec<String>, ()> {
+    let mut index = None;
+    let mut iter = args.iter().enumerate();
+    while let Some((i, arg)) = iter.next() {
+        match arg.as_str() {
+            "--" => {
+                index = Some(i + 1).filter(|x| *x < args.len());
+                break;
+            }
+            "-p" => {
+                iter.next(); // mosh's udp port
+            }
+            x if x.starts_with('-') => {}
+            _ => {
+                index = Some(i);
+                break;
+            }
+        }
+    }
+    let Some(index) = index else {
+        error!("no destination found in the arguments of 'mosh'");
+        return Err(());
+    };
+
+    let destination = &args[index];
+    let (user, alias) = match destination.split_once('@') {
+        Some((user, alias)) => (Some(user), alias),
+        None => (None, destination.as_str()),
+    };
+    let Some(record) = crate::find_record(alias)? else {
+        return Ok(args.to_vec());
+    };
+
+    let mut result = Vec::with_capacity(args.len() + 1);
+    if !args.iter().any(|x| x.starts_with("--ssh")) {
+        result.push(format!("--ssh={} -p {}", quote(ssh), record.port));
+    }
+    for (i, arg) in args.iter().enumerate() {
+        if i == index {
+            let user = user.unwrap_or(&record.username);
+            result.push(format!("{}@{}", user, record.host));
+        } else {
+            result.push(arg.clone());
+        }
+    }
+    Ok(result)
+}
+
+/// Options of ssh which take an argument, see ssh(1).
+const SSH_OPTIONS_WITH_ARG: &str = "BbcDEeFIiJLlmOoPpQRSWw";
+
+/// Returns the index of the destination in the arguments of `ssh`, skipping its options.
+pub fn ssh_destination(args: &[String]) -> Option<usize> {
+    let mut iter = args.iter().enumerate();
+    while let Some((i, arg)) = iter.next() {
+        if arg == "--" {
+            return Some(i + 1).filter(|x| *x < args.len());
+        }
+        let Some(flags) = arg.strip_prefix('-').filter(|x| !x.is_empty()) else {
+            return Some(i);
+        };
+        // Flags may be grouped, e.g. '-tp2222' or '-tp 2222'.
+        for (j, flag) in flags.char_indices() {
+            if SSH_OPTIONS_WITH_ARG.contains(flag) {
+                if j + 1 == flags.len() {
+                    iter.next();
+                }
+                break;
+            }
+        }
+    }
+    None
+}
+
+/// Returns where options can be inserted before the destination at `index`,
+/// which is before a '--' separating it.
+fn options_position(args: &[String], index: usize) -> usize {
+    if index > 0 && args[index - 1] == "--" {
+        index - 1
+    } else {
+        index
+    }
+}
+
+/// Whether any entry runs a command on connect. ssh refuses to run that along with
+/// the command or subsystem of another tool, so they have to turn it off.
+fn has_startup_commands() -> Result<bool, ()> {
+    Ok(crate::load_entries()?.iter().any(|x| x.has_startup_command()))
+}
+
+/// Turns off the command an entry runs on connect if `args` give one themselves,
+/// and lets ssh decide on a terminal as it does for any remote command.
+fn skip_startup_command(args: &[String]) -> Result<Vec<String>, ()> {
+    let Some(index) = ssh_destination(args) else {
+        return Ok(args.to_vec());
+    };
+    // ssh takes options after the destination too, the command starts after them.
+    if ssh_destination(&args[index + 1..]).is_none() {
+        return Ok(args.to_vec());
+    }
+    let destination = &args[index];
+    let alias = destination
+        .rsplit_once('@')
+        .map_or(destination.as_str(), |(_, x)| x);
+    let entries = crate::load_entries()?;
+    if !entries
+        .iter()
+        .any(|x| x.has_alias(alias) && x.has_startup_command())
+    {
+        return Ok(args.to_vec());
+    }
+    let at = options_position(args, index);
+    let mut result = args[..at].to_vec();
+    result.extend(["-o", "RemoteCommand=none", "-o", "RequestTTY=auto"].map(str::to_string));
+    result.extend_from_slice(&args[at..]);
+    Ok(result)
+}
+
+/// Whether the destination is an ad-hoc `ssh://user@host:port` or `user@host:port` URI,
+/// rather than an alias or a plain `user@host` ssh understands by itself.
+fn is_uri(destination: &str) -> bool {
+    if destination.starts_with("ssh://") {
+        return true;
+    }
+    match destination.split_once('@') {
+        Some((_, host)) => host.starts_with('[') || host.contains(':'),
+        None => false,
+    }
+}
+
+/// Translates an ad-hoc URI destination of the `ssh` arguments into `-p port -l user host`.
+/// The arguments are returned unchanged if the destination is not such a URI.
+pub fn translate_uri_args(args: &[String]) -> Result<Vec<String>, ()> {
+    let Some(index) = ssh_destination(args) else {
+        return Ok(args.to_vec());
+    };
+    if !is_uri(&args[index]) {
+        return Ok(args.to_vec());
+    }
+    let (user, host, port) = crate::split_uri(&args[index])?;
+
+    let at = options_position(args, index);
+    let mut result = Vec::with_capacity(args.len() + 4);
+    result.extend_from_slice(&args[..at]);
+    if let Some(port) = port {
+        result.push("-p".to_string());
+        result.push(port.to_string());
+    }
+    result.push("-l".to_string());
+    result.push(user.to_string());
+    result.extend_from_slice(&args[at..index]);
+    result.push(host.to_string());
+    result.extend_from_slice(&args[index + 1..]);
+    Ok(result)
+}
@@ -0,0 +1,373 @@
+use std::io::ErrorKind;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use simple_logger::{custom, error, warn};
+
+use crate::transport::{self, Tool};
+use crate::{data_dir, load_entries, override_system_entry, update_entries};
+
+/// Tunnels are stored as `tunnel.<name>=<forwards>` metadata of the entry they connect through.
+const META_KEY_PREFIX: &str = "tunnel.";
+
+struct Forward {
+    option: &'static str,
+    spec: String,
+    local_port: Option<(String, u16)>,
+}
+
+/// Parses a forward spec: `L:[bind:]port:host:hostport`, `R:[bind:]port:host:hostport`
+/// or `D:[bind:]port`.
+fn parse_forward(s: &str) -> Result<Forward, ()> {
+    let invalid = || {
+        error!("invalid forward '{}'", s);
+    };
+    let (kind, spec) = s.split_once(':').ok_or_else(invalid)?;
+    let parts: Vec<&str> = spec.split(':').collect();
+    let (option, local) = match (kind.to_ascii_uppercase().as_str(), parts.len()) {
+        ("L", 3) => ("-L", Some((None, parts[0]))),
+        ("L", 4) => ("-L", Some((Some(parts[0]), parts[1]))),
+        ("D", 1) => ("-D", Some((None, parts[0]))),
+        ("D", 2) => ("-D", Some((Some(parts[0]), parts[1]))),
+        ("R", 3 | 4) => ("-R", None),
+        _ => {
+            invalid();
+            return Err(());
+        }
+    };
+    let local_port = match local {
+        Some((bind, port)) => {
+            let port = port.parse().map_err(|_| invalid())?;
+            // An empty address or '*' binds all interfaces, see ssh(1).
+            let bind = match bind {
+                None | Some("localhost") => "127.0.0.1",
+                Some("") | Some("*") => "0.0.0.0",
+                Some(x) => x,
+            };
+            Some((bind.to_string(), port))
+        }
+        None => None,
+    };
+    Ok(Forward {
+        option,
+        spec: spec.to_string(),
+        local_port,
+    })
+}
+
+fn parse_forwards(s: &str) -> Result<Vec<Forward>, ()> {
+    s.split_whitespace().map(parse_forward).collect()
+}
+
+/// Returns all tunnels as `(name, alias, forwards)`.
+fn load_tunnels() -> Result<Vec<(String, String, String)>, ()> {
+    let mut result = Vec::new();
+    for entry in load_entries()? {
+        for (k, v) in &entry.meta {
+            if let Some(name) = k.strip_prefix(META_KEY_PREFIX) {
+                result.push((name.to_string(), entry.host.clone(), v.clone()));
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn find_tunnel(name: &str) -> Result<(String, Vec<Forward>), ()> {
+    let (_, alias, forwards) = load_tunnels()?
+        .into_iter()
+        .find(|(x, _, _)| x == name)
+        .ok_or_else(|| {
+            error!("tunnel '{}' not found", name);
+        })?;
+    Ok((alias, parse_forwards(&forwards)?))
+}
+
+pub fn add_tunnel(name: &str, alias: &str, forwards: &[String]) -> Result<(), ()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || x == '-' || x == '_')
+    {
+        error!("invalid tunnel name '{}'", name);
+        return Err(());
+    }
+    if forwards.is_empty() {
+        error!("tunnel '{}' requires at least one forward", name);
+        return Err(());
+    }
+    for forward in forwards {
+        parse_forward(forward)?;
+    }
+    let key = format!("{}{}", META_KEY_PREFIX, name);
+    update_entries(|entries| {
+        if let Some(entry) = entries
+            .iter()
+            .find(|x| x.host != alias && x.meta(&key).is_some())
+        {
+            error!("tunnel '{}' already exists on '{}'", name, entry.host);
+            return Err(());
+        }
+        override_system_entry(entries, alias);
+        let entry = entries
+            .iter_mut()
+            .find(|x| x.has_alias(alias))
+            .ok_or_else(|| {
+                error!("entry '{}' not found", alias);
+            })?;
+        entry.set_meta(&key, Some(forwards.join(" ")));
+        Ok(())
+    })
+}
+
+pub fn remove_tunnel(name: &str) -> Result<(), ()> {
+    if running_pid(name)?.is_some() {
+        error!("tunnel '{}' is running, stop it first", name);
+        return Err(());
+    }
+    let key = format!("{}{}", META_KEY_PREFIX, name);
+    update_entries(|entries| {
+        let entry = entries
+            .iter_mut()
+            .find(|x| x.meta(&key).is_some())
+            .ok_or_else(|| {
+                error!("tunnel '{}' not found", name);
+            })?;
+        entry.set_meta(&key, None);
+        Ok(())
+    })
+}
+
+pub fn list_tunnels() -> Result<(), ()> {
+    let tunnels = load_tunnels()?;
+    if tunnels.is_empty() {
+        warn!("no tunnels");
+        return Ok(());
+    }
+    for (name, alias, forwards) in tunnels {
+        let state = match running_pid(&name)? {
+            Some(pid) => format!("up (pid {})", pid),
+            None => "down".to_string(),
+        };
+        custom!(title=name; "{} via {}, {}", forwards, alias, state);
+    }
+    Ok(())
+}
+
+/// Starts the tunnel as a background `ssh -N` and records its pid.
+pub fn start_tunnel(name: &str) -> Result<(), ()> {
+    let (alias, forwards) = find_tunnel(name)?;
+    if let Some(pid) = running_pid(name)? {
+        error!("tunnel '{}' is already up (pid {})", name, pid);
+        return Err(());
+    }
+    for (bind, port) in forwards.iter().filter_map(|x| x.local_port.as_ref()) {
+        if let Err(e) = TcpListener::bind((bind.as_str(), *port)) {
+            error!("local port {}:{} is not available: {}", bind, port, e);
+            return Err(());
+        }
+    }
+
+    let mut args = vec![
+        "-N".to_string(),
+        "-o".to_string(),
+        "ExitOnForwardFailure=yes".to_string(),
+    ];
+    for forward in &forwards {
+        args.push(forward.option.to_string());
+        args.push(forward.spec.clone());
+    }
+    args.push(alias);
+    let mut cmd = transport::command(Tool::Ssh, &args)?;
+    cmd.stdin(Stdio::null()).stdout(Stdio::null());
+    detach(&mut cmd);
+    let mut child = cmd.spawn().map_err(|e| {
+        error!("failed to spawn child process: {}", e);
+    })?;
+
+    // Give ssh a moment to fail on authentication or forwarding errors.
+    std::thread::sleep(Duration::from_secs(1));
+    if let Ok(Some(status)) = child.try_wait() {
+        error!("tunnel '{}' exited immediately: {}", name, status);
+        return Err(());
+    }
+    write_pid(name, child.id())?;
+    custom!(title=name; "up (pid {})", child.id());
+    Ok(())
+}
+
+pub fn stop_tunnel(name: &str) -> Result<(), ()> {
+    if read_pid(name)?.is_none() {
+        error!("tunnel '{}' is not up", name);
+        return Err(());
+    }
+    match running_pid(name)? {
+        Some(pid) => kill(pid)?,
+        None => warn!("tunnel '{}' has already exited", name),
+    }
+    let path = pid_path(name)?;
+    std::fs::remove_file(&path).map_err(|e| {
+        error!("failed to remove '{}': {}", path.display(), e);
+    })?;
+    custom!(title=name; "down");
+    Ok(())
+}
+
+fn pid_path(name: &str) -> Result<PathBuf, ()> {
+    Ok(data_dir()?.join("tunnels").join(format!("{}.pid", name)))
+}
+
+/// Reads the pid file, `<pid> <start time>`; see [`start_time`].
+fn read_pid(name: &str) -> Result<Option<(u32, String)>, ()> {
+    let path = pid_path(name)?;
+    match std::fs::read_to_string(&path) {
+        Ok(content) => {
+            let (pid, start) = content.trim().split_once(' ').unwrap_or((content.trim(), ""));
+            Ok(pid.parse().ok().map(|x| (x, start.to_string())))
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => {
+            error!("failed to read '{}': {}", path.display(), e);
+            Err(())
+        }
+    }
+}
+
+/// Returns the pid of the tunnel if it is still the process which was started.
+/// After a reboot the pid may belong to another process, so the pid file is
+/// stale unless the start time matches too.
+fn running_pid(name: &str) -> Result<Option<u32>, ()> {
+    let running = read_pid(name)?.filter(|(pid, start)| {
+        is_alive(*pid) && !start.is_empty() && start_time(*pid).as_ref() == Some(start)
+    });
+    Ok(running.map(|(pid, _)| pid))
+}
+
+fn write_pid(name: &str, pid: u32) -> Result<(), ()> {
+    let Some(start) = start_time(pid) else {
+        error!("failed to get the start time of process {}", pid);
+        return Err(());
+    };
+    let path = pid_path(name)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| {
+            error!("failed to create directory '{}': {}", dir.display(), e);
+        })?;
+    }
+    std::fs::write(&path, format!("{} {}", pid, start)).map_err(|e| {
+        error!("failed to write '{}': {}", path.display(), e);
+    })
+}
+
+/// Keeps the tunnel alive after the terminal sends Ctrl-C to the foreground group.
+#[cfg(unix)]
+fn detach(cmd: &mut std::process::Command) {
+    use std::os::unix::process::CommandExt;
+    cmd.process_group(0);
+}
+
+#[cfg(windows)]
+fn detach(cmd: &mut std::process::Command) {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+    cmd.creation_flags(CREATE_NEW_PROCESS_GROUP);
+}
+
+#[cfg(unix)]
+fn is_alive(pid: u32) -> bool {
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+/// Returns an identifier of when the process `pid` started, in clock ticks since boot.
+#[cfg(target_os = "linux")]
+fn start_time(pid: u32) -> Option<String> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name in the second field may contain spaces; the start time is the 22nd field.
+    let fields = stat.get(stat.rfind(')')? + 1..)?;
+    fields.split_whitespace().nth(19).map(str::to_string)
+}
+
+/// Returns an identifier of when the process `pid` started, as printed by ps.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn start_time(pid: u32) -> Option<String> {
+    let output = std::process::Command::new("ps")
+        .args(["-o", "lstart=", "-p", &pid.to_string()])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let start = String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("_");
+    (output.status.success() && !start.is_empty()).then_some(start)
+}
+
+#[cfg(unix)]
+fn kill(pid: u32) -> Result<(), ()> {
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+        let e = std::io::Error::last_os_error();
+        error!("failed to kill process {}: {}", pid, e);
+        return Err(());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn is_alive(pid: u32) -> bool {
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::minwinbase::STILL_ACTIVE;
+    use winapi::um::processthreadsapi::{GetExitCodeProcess, OpenProcess};
+    use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return false;
+        }
+        let mut code = 0;
+        let ok = GetExitCodeProcess(handle, &mut code) != 0;
+        CloseHandle(handle);
+        ok && code == STILL_ACTIVE
+    }
+}
+
+/// Returns an identifier of when the process `pid` started, its creation time.
+#[cfg(windows)]
+fn start_time(pid: u32) -> Option<String> {
+    use winapi::shared::minwindef::FILETIME;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{GetProcessTimes, OpenProcess};
+    use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+    unsafe {
+        let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if handle.is_null() {
+            return None;
+        }
+        let mut times: [FILETIME; 4] = std::mem::zeroed();
+        let [creation, exit, kernel, user] = &mut times;
+        let ok = GetProcessTimes(handle, creation, exit, kernel, user) != 0;
+        CloseHandle(handle);
+        let time = (u64::from(times[0].dwHighDateTime) << 32) | u64::from(times[0].dwLowDateTime);
+        ok.then(|| time.to_string())
+    }
+}
+
+#[cfg(windows)]
+fn kill(pid: u32) -> Result<(), ()> {
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::processthreadsapi::{OpenProcess, TerminateProcess};
+    use winapi::um::winnt::PROCESS_TERMINATE;
+    unsafe {
+        let handle = OpenProcess(PROCESS_TERMINATE, 0, pid);
+        if handle.is_null() || TerminateProcess(handle, 1) == 0 {
+            let e = std::io::Error::last_os_error();
+            if !handle.is_null() {
+                CloseHandle(handle);
+            }
+            error!("failed to kill process {}: {}", pid, e);
+            return Err(());
+        }
+        CloseHandle(handle);
+    }
+    Ok(())
+}